@@ -0,0 +1,83 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::{DiffFile, DiffSettings, FileSummary};
+
+/// Number of diffs kept before the oldest is dropped.
+const MAX_ENTRIES: usize = 16;
+
+/// Identifies a tree-to-tree diff. Trees are immutable, so anything computed
+/// for a key stays valid. Diffs against a working directory or index have no
+/// key and are never cached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DiffKey {
+    pub from_tree: git2::Oid,
+    pub to_tree: git2::Oid,
+    pub settings: DiffSettings,
+}
+
+/// What has been computed so far for one diff. A `git2::Diff` borrows its
+/// repository and can't be shared across commands, so its results are kept
+/// instead.
+#[derive(Debug, Default)]
+pub struct CachedDiff {
+    pub files: Option<Vec<FileSummary>>,
+    /// Fully loaded files, keyed by path and the changed lines before them,
+    /// which the total-lines limit counts against.
+    pub file_diffs: HashMap<(String, usize), DiffFile>,
+}
+
+#[derive(Debug, Default)]
+pub struct DiffCache {
+    entries: HashMap<DiffKey, CachedDiff>,
+    order: VecDeque<DiffKey>,
+}
+
+impl DiffCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entry for `key`, created empty if missing.
+    pub fn entry(&mut self, key: DiffKey) -> &mut CachedDiff {
+        if !self.entries.contains_key(&key) {
+            while self.order.len() >= MAX_ENTRIES {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.entries.remove(&oldest);
+                }
+            }
+            self.order.push_back(key.clone());
+        }
+        self.entries.entry(key).or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> DiffKey {
+        DiffKey {
+            from_tree: git2::Oid::from_bytes(&[0; 20]).unwrap(),
+            to_tree: git2::Oid::from_bytes(&[n; 20]).unwrap(),
+            settings: DiffSettings::default(),
+        }
+    }
+
+    #[test]
+    fn test_entry_reuses_and_evicts_oldest() {
+        let mut cache = DiffCache::new();
+        cache.entry(key(1)).files = Some(Vec::new());
+        assert!(cache.entry(key(1)).files.is_some());
+
+        for n in 2..=MAX_ENTRIES as u8 + 1 {
+            cache.entry(key(n));
+        }
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+        assert!(cache.entry(key(1)).files.is_none());
+    }
+}
@@ -1,8 +1,20 @@
 use tauri::State;
 
+use crate::curate;
+use crate::dirdiff;
+use crate::export;
 use crate::git;
+use crate::merge;
+use crate::patchfile;
+use crate::rangediff;
+use crate::rounds;
+use crate::settings;
 use crate::state::AppState;
-use crate::types::{DiffResult, RefInfo};
+use crate::types::{
+    ApplyResult, ApplyTarget, BlobPair, CommitInfo, CommitPair, CommitRange, CompareMode, DiffFile,
+    DiffLimits, DiffParams, DiffResult, DiffSettings, DiffSide, DiffSummary, FileLines,
+    FileSelection, MergeOutcome, MergePreview, MergeStrategy, PatchSeries, RefInfo, ReviewRound,
+};
 
 #[tauri::command]
 pub async fn open_repo(path: String, state: State<'_, AppState>) -> Result<Vec<RefInfo>, String> {
@@ -10,6 +22,7 @@ pub async fn open_repo(path: String, state: State<'_, AppState>) -> Result<Vec<R
     let refs = git::list_refs(&repo)?;
     let mut repo_path = state.repo_path.lock().await;
     *repo_path = Some(path);
+    state.diff_cache.lock().await.clear();
     Ok(refs)
 }
 
@@ -25,12 +38,319 @@ pub async fn get_refs(state: State<'_, AppState>) -> Result<Vec<RefInfo>, String
 pub async fn get_diff(
     base: String,
     compare: String,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
     state: State<'_, AppState>,
 ) -> Result<DiffResult, String> {
     let repo_path = state.repo_path.lock().await;
     let path = repo_path.as_deref().ok_or("No repo opened")?;
     let repo = git::discover_repo(path)?;
-    git::generate_diff(&repo, &base, &compare)
+    let params = diff_params(&repo, mode, merge_base, commits, settings)?;
+    git::generate_diff(&repo, &base, &compare, &params)
+}
+
+#[tauri::command]
+pub async fn get_diff_summary(
+    base: String,
+    compare: String,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
+    state: State<'_, AppState>,
+) -> Result<DiffSummary, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    let params = diff_params(&repo, mode, merge_base, commits, settings)?;
+    let mut cache = state.diff_cache.lock().await;
+    git::diff_summary(&repo, &base, &compare, &params, &mut cache)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_file_diff(
+    base: String,
+    compare: String,
+    path: String,
+    old_path: Option<String>,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
+    lines_before: Option<usize>,
+    load_anyway: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<DiffFile, String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    let mut params = diff_params(&repo, mode, merge_base, commits, settings)?;
+    // The reviewer asked for the whole file despite the limits.
+    if load_anyway.unwrap_or(false) {
+        params.settings.limits = DiffLimits::unlimited();
+    }
+    let mut cache = state.diff_cache.lock().await;
+    git::file_diff(
+        &repo,
+        &base,
+        &compare,
+        &params,
+        &path,
+        old_path.as_deref(),
+        lines_before.unwrap_or(0),
+        &mut cache,
+    )
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_selection(
+    base: String,
+    compare: String,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
+    selection: Vec<FileSelection>,
+    target: ApplyTarget,
+    state: State<'_, AppState>,
+) -> Result<ApplyResult, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    let params = diff_params(&repo, mode, merge_base, commits, settings)?;
+    curate::apply_selection(&repo, &base, &compare, &params, &selection, &target)
+}
+
+/// Write the diff, or the selected part of it, to `path` as a unified patch.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_patch(
+    base: String,
+    compare: String,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
+    selection: Option<Vec<FileSelection>>,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    let params = diff_params(&repo, mode, merge_base, commits, settings)?;
+    let patch = export::unified_patch(&repo, &base, &compare, &params, selection.as_deref())?;
+    std::fs::write(&path, patch).map_err(|e| format!("Failed to write '{}': {}", path, e))
+}
+
+/// Write the commits in base..compare to `path` as a format-patch mbox.
+#[tauri::command]
+pub async fn export_mbox(
+    base: String,
+    compare: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    let mbox = export::format_patch(&repo, &base, &compare)?;
+    std::fs::write(&path, mbox).map_err(|e| format!("Failed to write '{}': {}", path, e))
+}
+
+/// Compare two directories or two files on disk, without a repository.
+#[tauri::command]
+pub async fn compare_paths(
+    old: String,
+    new: String,
+    ignore: Vec<String>,
+    settings: Option<DiffSettings>,
+) -> Result<DiffResult, String> {
+    dirdiff::compare_paths(&old, &new, &ignore, &settings.unwrap_or_default())
+}
+
+/// Parse a patch file or format-patch mbox for review without a repository.
+#[tauri::command]
+pub async fn open_patch_file(
+    path: String,
+    settings: Option<DiffSettings>,
+) -> Result<PatchSeries, String> {
+    patchfile::open_patch_file(&path, &settings.unwrap_or_default().limits)
+}
+
+/// Fill in unspecified diff parameters, taking settings from the repository's
+/// saved preferences.
+fn diff_params(
+    repo: &git2::Repository,
+    mode: Option<CompareMode>,
+    merge_base: Option<bool>,
+    commits: Option<CommitRange>,
+    settings: Option<DiffSettings>,
+) -> Result<DiffParams, String> {
+    let settings = match settings {
+        Some(settings) => settings,
+        None => settings::load_diff_settings(repo)?,
+    };
+    Ok(DiffParams {
+        mode: mode.unwrap_or_default(),
+        merge_base: merge_base.unwrap_or(false),
+        commits,
+        settings,
+    })
+}
+
+#[tauri::command]
+pub async fn list_commits(
+    base: String,
+    compare: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<CommitInfo>, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    git::list_commits(&repo, &base, &compare)
+}
+
+#[tauri::command]
+pub async fn record_review_round(
+    compare: String,
+    state: State<'_, AppState>,
+) -> Result<ReviewRound, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    rounds::record_round(&repo, &compare)
+}
+
+#[tauri::command]
+pub async fn list_review_rounds(
+    compare: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<ReviewRound>, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    rounds::list_rounds(&repo, &compare)
+}
+
+#[tauri::command]
+pub async fn get_range_diff(
+    base: String,
+    old: String,
+    new: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<CommitPair>, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    rangediff::range_diff(&repo, &base, &old, &new)
+}
+
+#[tauri::command]
+pub async fn get_merge_preview(
+    base: String,
+    compare: String,
+    state: State<'_, AppState>,
+) -> Result<MergePreview, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    merge::merge_preview(&repo, &base, &compare)
+}
+
+#[tauri::command]
+pub async fn merge_into_base(
+    base: String,
+    compare: String,
+    strategy: MergeStrategy,
+    message: Option<String>,
+    delete_compare: bool,
+    state: State<'_, AppState>,
+) -> Result<MergeOutcome, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    merge::merge_into_base(
+        &repo,
+        &base,
+        &compare,
+        strategy,
+        message.as_deref(),
+        delete_compare,
+    )
+}
+
+#[tauri::command]
+pub async fn get_diff_settings(state: State<'_, AppState>) -> Result<DiffSettings, String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    settings::load_diff_settings(&repo)
+}
+
+#[tauri::command]
+pub async fn set_diff_settings(
+    settings: DiffSettings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo_path = state.repo_path.lock().await;
+    let path = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(path)?;
+    settings::save_diff_settings(&repo, &settings)
+}
+
+#[tauri::command]
+pub async fn get_blob_pair(
+    compare: String,
+    path: String,
+    old_path: Option<String>,
+    old_oid: Option<String>,
+    new_oid: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<BlobPair, String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    git::blob_pair(
+        &repo,
+        &compare,
+        &path,
+        old_path.as_deref(),
+        old_oid.as_deref(),
+        new_oid.as_deref(),
+    )
+}
+
+#[tauri::command]
+pub async fn get_blob_lines(
+    compare: String,
+    path: String,
+    oid: String,
+    start: u32,
+    end: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<FileLines, String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    git::blob_lines(&repo, &compare, &path, &oid, start, end)
+}
+
+#[tauri::command]
+pub async fn get_file_at_ref(
+    spec: String,
+    side: DiffSide,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<FileLines, String> {
+    let repo_path = state.repo_path.lock().await;
+    let repo_dir = repo_path.as_deref().ok_or("No repo opened")?;
+    let repo = git::discover_repo(repo_dir)?;
+    git::file_at_ref(&repo, &spec, side, &path)
 }
 
 #[tauri::command]
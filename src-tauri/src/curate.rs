@@ -0,0 +1,391 @@
+use git2::{Delta, Diff, Patch, Repository};
+
+use crate::git::{self, DiffPatch};
+use crate::types::{ApplyResult, ApplyTarget, DiffParams, FileSelection, HunkSelection};
+
+/// Apply only the accepted parts of the base..compare diff, either as a commit
+/// on a new branch or to base's checkout.
+///
+/// The selection is applied as a patch through libgit2, so a curated branch
+/// starts from the same tree the diff does (base, or the merge-base).
+pub fn apply_selection(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+    selection: &[FileSelection],
+    target: &ApplyTarget,
+) -> Result<ApplyResult, String> {
+    let paths: Vec<&str> = selection
+        .iter()
+        .flat_map(|f| std::iter::once(f.path.as_str()).chain(f.old_path.as_deref()))
+        .collect();
+    if paths.is_empty() {
+        return Err("No changes selected".into());
+    }
+
+    let patch = git::diff_patch(repo, base, compare, params, &paths)?;
+    let text = select_changes(&patch.text, selection)?;
+    if text.is_empty() {
+        return Err("No changes selected".into());
+    }
+    let diff =
+        Diff::from_buffer(&text).map_err(|e| format!("Failed to parse selected changes: {}", e))?;
+    let files = diff.deltas().map(|delta| git::delta_path(&delta)).collect();
+
+    let commit = match target {
+        ApplyTarget::Branch { name, message } => {
+            Some(commit_to_branch(repo, &patch, &diff, name, message)?.to_string())
+        }
+        ApplyTarget::Worktree => {
+            let checkout = git::branch_checkout(repo, base)?
+                .ok_or_else(|| format!("'{}' isn't checked out in any worktree", base))?;
+            checkout
+                .apply(&diff, git2::ApplyLocation::WorkDir, None)
+                .map_err(|e| format!("Failed to apply changes to '{}': {}", base, e))?;
+            None
+        }
+    };
+    Ok(ApplyResult { commit, files })
+}
+
+/// Rewrite a patch so it only carries the selected files, hunks and lines.
+pub fn select_changes(text: &[u8], selection: &[FileSelection]) -> Result<Vec<u8>, String> {
+    let diff = Diff::from_buffer(text).map_err(|e| format!("Failed to parse diff: {}", e))?;
+    let mut out = Vec::new();
+    for idx in 0..diff.deltas().len() {
+        let delta = diff.get_delta(idx).unwrap();
+        let path = git::delta_path(&delta);
+        let Some(file) = selection.iter().find(|f| f.path == path) else {
+            continue;
+        };
+        let mut patch = Patch::from_diff(&diff, idx)
+            .map_err(|e| format!("Failed to read patch for '{}': {}", path, e))?
+            .ok_or_else(|| format!("'{}' has no changes in this diff", path))?;
+        let full = patch
+            .to_buf()
+            .map_err(|e| format!("Failed to format patch for '{}': {}", path, e))?;
+
+        match &file.hunks {
+            None => out.extend_from_slice(&full),
+            Some(hunks) => select_hunks(&patch, delta.status(), &full, hunks, &path, &mut out)?,
+        }
+    }
+    Ok(out)
+}
+
+/// Append the selected hunks of one file's patch to `out`. Rejected additions
+/// are dropped and rejected deletions become context, the way `git add -p`
+/// edits a hunk.
+fn select_hunks(
+    patch: &Patch,
+    status: Delta,
+    full: &[u8],
+    selection: &[HunkSelection],
+    path: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    if let Some(hunk) = selection
+        .iter()
+        .find(|h| h.index as usize >= patch.num_hunks())
+    {
+        return Err(format!("'{}' has no hunk {}", path, hunk.index));
+    }
+
+    let mut body = Vec::new();
+    let mut everything = true;
+    let mut offset: i64 = 0;
+    for hunk_idx in 0..patch.num_hunks() {
+        let Some(chosen) = selection.iter().find(|h| h.index as usize == hunk_idx) else {
+            everything = false;
+            continue;
+        };
+        let (hunk, _) = patch
+            .hunk(hunk_idx)
+            .map_err(|e| format!("Failed to read hunk of '{}': {}", path, e))?;
+
+        let mut lines = Vec::new();
+        let (mut old_count, mut new_count, mut changed) = (0u32, 0u32, false);
+        // Numbered like `DiffHunk::lines`, which leaves out the "\ No newline
+        // at end of file" markers.
+        let mut line_idx = 0;
+        for raw_idx in 0..patch.num_lines_in_hunk(hunk_idx).unwrap_or(0) {
+            let line = patch
+                .line_in_hunk(hunk_idx, raw_idx)
+                .map_err(|e| format!("Failed to read hunk of '{}': {}", path, e))?;
+            if !matches!(line.origin(), '+' | '-' | ' ') {
+                continue;
+            }
+            let accepted = chosen
+                .lines
+                .as_ref()
+                .is_none_or(|accepted| accepted.contains(&line_idx));
+            line_idx += 1;
+
+            let origin = match (line.origin(), accepted) {
+                ('+', false) => {
+                    everything = false;
+                    continue;
+                }
+                ('-', false) => {
+                    everything = false;
+                    ' '
+                }
+                (origin, _) => origin,
+            };
+            changed |= origin != ' ';
+            if origin != '+' {
+                old_count += 1;
+            }
+            if origin != '-' {
+                new_count += 1;
+            }
+            lines.push(origin as u8);
+            lines.extend_from_slice(line.content());
+            if !line.content().ends_with(b"\n") {
+                lines.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+        if !changed {
+            continue;
+        }
+
+        let old_start = hunk.old_start() as i64;
+        let new_start = old_start
+            + offset
+            + match (old_count, new_count) {
+                // Zero-length ranges point at the line before the change.
+                (0, _) => 1,
+                (_, 0) => -1,
+                _ => 0,
+            };
+        offset += new_count as i64 - old_count as i64;
+        body.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@\n",
+                hunk_range(old_start, old_count),
+                hunk_range(new_start, new_count)
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&lines);
+    }
+
+    if everything {
+        out.extend_from_slice(full);
+    } else if !body.is_empty() {
+        if status == Delta::Deleted {
+            // Part of the file survives, so this is no longer a deletion.
+            out.extend_from_slice(
+                format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n", path).as_bytes(),
+            );
+        } else {
+            let header_len = full
+                .windows(4)
+                .position(|w| w == b"\n@@ ")
+                .map_or(full.len(), |pos| pos + 1);
+            out.extend_from_slice(&full[..header_len]);
+        }
+        out.extend_from_slice(&body);
+    }
+    Ok(())
+}
+
+fn hunk_range(start: i64, count: u32) -> String {
+    if count == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+fn commit_to_branch(
+    repo: &Repository,
+    patch: &DiffPatch,
+    diff: &Diff,
+    name: &str,
+    message: &str,
+) -> Result<git2::Oid, String> {
+    if message.trim().is_empty() {
+        return Err("Commit message is empty".into());
+    }
+    if repo.find_branch(name, git2::BranchType::Local).is_ok() {
+        return Err(format!("Branch '{}' already exists", name));
+    }
+
+    let from_tree = repo
+        .find_tree(patch.from_tree)
+        .map_err(|e| format!("Failed to load base tree: {}", e))?;
+    let tree = repo
+        .apply_to_tree(&from_tree, diff, None)
+        .and_then(|mut index| index.write_tree_to(repo))
+        .and_then(|oid| repo.find_tree(oid))
+        .map_err(|e| format!("Failed to apply selected changes: {}", e))?;
+    let parent = patch
+        .from_commit
+        .map(|oid| repo.find_commit(oid))
+        .transpose()
+        .map_err(|e| format!("Failed to read base commit: {}", e))?;
+    let sig = repo
+        .signature()
+        .map_err(|e| format!("Failed to read git identity: {}", e))?;
+
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let oid = repo
+        .commit(None, &sig, &sig, message, &tree, &parents)
+        .map_err(|e| format!("Failed to create commit: {}", e))?;
+    repo.find_commit(oid)
+        .and_then(|commit| repo.branch(name, &commit, false))
+        .map_err(|e| format!("Failed to create branch '{}': {}", name, e))?;
+    Ok(oid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_support::commit_to_head;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, Repository, git2::Oid, git2::Oid) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        {
+            let mut config = repo.config().unwrap();
+            config.set_str("user.name", "Test").unwrap();
+            config.set_str("user.email", "test@test.com").unwrap();
+        }
+
+        let before: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let after = before
+            .replace("line 2\n", "line two\n")
+            .replace("line 18\n", "line 18\nline 18.5\n");
+        let base = commit_to_head(
+            &repo,
+            &[("a.txt", Some(&before)), ("gone.txt", Some("x\ny\n"))],
+            "commit",
+        );
+        let compare = commit_to_head(
+            &repo,
+            &[
+                ("a.txt", Some(&after)),
+                ("b.txt", Some("new\n")),
+                ("gone.txt", None),
+            ],
+            "commit",
+        );
+        (dir, repo, base, compare)
+    }
+
+    fn blob_at(repo: &Repository, commit: &str, path: &str) -> Option<String> {
+        let tree = repo
+            .find_commit(git2::Oid::from_str(commit).unwrap())
+            .unwrap()
+            .tree()
+            .unwrap();
+        let entry = tree.get_path(std::path::Path::new(path)).ok()?;
+        let blob = repo.find_blob(entry.id()).unwrap();
+        Some(String::from_utf8(blob.content().to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_apply_selected_hunks_and_lines_to_branch() {
+        let (_dir, repo, base, compare) = setup();
+        let selection = vec![
+            FileSelection {
+                path: "a.txt".into(),
+                old_path: None,
+                // Only the second hunk.
+                hunks: Some(vec![HunkSelection {
+                    index: 1,
+                    lines: None,
+                }]),
+            },
+            FileSelection {
+                path: "gone.txt".into(),
+                old_path: None,
+                // Keep "x", delete "y".
+                hunks: Some(vec![HunkSelection {
+                    index: 0,
+                    lines: Some(vec![1]),
+                }]),
+            },
+        ];
+        let target = ApplyTarget::Branch {
+            name: "curated".into(),
+            message: "Take some of it".into(),
+        };
+        let result = apply_selection(
+            &repo,
+            &base.to_string(),
+            &compare.to_string(),
+            &DiffParams::default(),
+            &selection,
+            &target,
+        )
+        .unwrap();
+        assert_eq!(result.files, vec!["a.txt", "gone.txt"]);
+
+        let commit = result.commit.unwrap();
+        let branch = repo
+            .find_branch("curated", git2::BranchType::Local)
+            .unwrap();
+        assert_eq!(branch.get().target().unwrap().to_string(), commit);
+        let a = blob_at(&repo, &commit, "a.txt").unwrap();
+        assert!(a.contains("line 2\n") && a.contains("line 18.5\n"));
+        assert_eq!(blob_at(&repo, &commit, "gone.txt").as_deref(), Some("x\n"));
+        assert!(blob_at(&repo, &commit, "b.txt").is_none());
+
+        let again = apply_selection(
+            &repo,
+            &base.to_string(),
+            &compare.to_string(),
+            &DiffParams::default(),
+            &selection,
+            &target,
+        );
+        assert!(again.unwrap_err().contains("already exists"));
+    }
+
+    #[test]
+    fn test_apply_selection_to_base_worktree() {
+        let (dir, repo, base, compare) = setup();
+        // Check out base on its own branch.
+        let base_commit = repo.find_commit(base).unwrap();
+        repo.branch("reviewed", &base_commit, false).unwrap();
+        repo.set_head("refs/heads/reviewed").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let selection = vec![FileSelection {
+            path: "b.txt".into(),
+            old_path: None,
+            hunks: None,
+        }];
+        let result = apply_selection(
+            &repo,
+            "reviewed",
+            &compare.to_string(),
+            &DiffParams::default(),
+            &selection,
+            &ApplyTarget::Worktree,
+        )
+        .unwrap();
+        assert!(result.commit.is_none());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+            "new\n"
+        );
+
+        let err = apply_selection(
+            &repo,
+            &base.to_string(),
+            &compare.to_string(),
+            &DiffParams::default(),
+            &selection,
+            &ApplyTarget::Worktree,
+        )
+        .unwrap_err();
+        assert!(err.contains("isn't checked out"));
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::encoding;
+use crate::git;
+use crate::mime;
+use crate::stats;
+use crate::types::{
+    CompareMode, DiffFile, DiffLimits, DiffResult, DiffSettings, FileMode, FileStatus,
+    TruncationReason,
+};
+
+/// One file on disk, keyed by its path relative to the compared root.
+struct Entry {
+    path: PathBuf,
+    mode: FileMode,
+}
+
+/// A file's content and mode on one side of the compare.
+struct Side {
+    path: PathBuf,
+    mode: FileMode,
+    /// Only the first [`encoding::BINARY_CHECK_LEN`] bytes when the file is
+    /// over the size limit.
+    content: Vec<u8>,
+    size: u64,
+}
+
+impl Side {
+    fn is_partial(&self) -> bool {
+        (self.content.len() as u64) < self.size
+    }
+}
+
+/// Compare two directory trees, or two single files, outside of any
+/// repository. Files whose path matches one of the `ignore` globs are
+/// skipped on both sides.
+pub fn compare_paths(
+    old: &str,
+    new: &str,
+    ignore: &[String],
+    settings: &DiffSettings,
+) -> Result<DiffResult, String> {
+    let old_meta = fs::metadata(old).map_err(|e| format!("Failed to read '{}': {}", old, e))?;
+    let new_meta = fs::metadata(new).map_err(|e| format!("Failed to read '{}': {}", new, e))?;
+
+    let files = match (old_meta.is_dir(), new_meta.is_dir()) {
+        (true, true) => compare_dirs(Path::new(old), Path::new(new), ignore, settings)?,
+        (false, false) => compare_files(Path::new(old), Path::new(new), settings)?,
+        _ => {
+            return Err(format!(
+                "Can't compare a file with a directory: '{}' and '{}'",
+                old, new
+            ))
+        }
+    };
+
+    let stats = stats::diff_stats(
+        files
+            .iter()
+            .map(|f| (f.path.as_str(), f.additions, f.deletions)),
+    );
+    Ok(DiffResult {
+        base_ref: String::new(),
+        compare_ref: String::new(),
+        base_oid: String::new(),
+        compare_oid: String::new(),
+        mode: CompareMode::Tree,
+        merge_base: None,
+        ahead: 0,
+        behind: 0,
+        stats,
+        files,
+    })
+}
+
+fn compare_dirs(
+    old: &Path,
+    new: &Path,
+    ignore: &[String],
+    settings: &DiffSettings,
+) -> Result<Vec<DiffFile>, String> {
+    let mut old_entries = BTreeMap::new();
+    walk(old, old, ignore, &mut old_entries)?;
+    let mut new_entries = BTreeMap::new();
+    walk(new, new, ignore, &mut new_entries)?;
+
+    let mut paths: Vec<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut files = Vec::new();
+    let mut lines_before = 0;
+    for path in paths {
+        let read = |entry| read_side(entry, &settings.limits);
+        let old_side = old_entries.get(path).map(read).transpose()?;
+        let new_side = new_entries.get(path).map(read).transpose()?;
+        let (old_side, new_side) = (old_side.as_ref(), new_side.as_ref());
+        if let Some(file) = diff_file(path, None, old_side, new_side, settings, lines_before) {
+            lines_before += file.additions + file.deletions;
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// Two single files are shown under the new file's name, as a rename when
+/// the names differ.
+fn compare_files(old: &Path, new: &Path, settings: &DiffSettings) -> Result<Vec<DiffFile>, String> {
+    let name = |path: &Path| {
+        path.file_name()
+            .map_or_else(|| path.to_string_lossy(), |n| n.to_string_lossy())
+            .into_owned()
+    };
+    let old_name = name(old);
+    let new_name = name(new);
+    let old_side = read_side(&entry(old.to_path_buf())?, &settings.limits)?;
+    let new_side = read_side(&entry(new.to_path_buf())?, &settings.limits)?;
+
+    let renamed_from = (old_name != new_name).then_some(old_name.as_str());
+    Ok(diff_file(
+        &new_name,
+        renamed_from,
+        Some(&old_side),
+        Some(&new_side),
+        settings,
+        0,
+    )
+    .into_iter()
+    .collect())
+}
+
+/// Collect the files under `dir`, keyed by `/`-separated paths relative to
+/// `root`. Ignored directories aren't descended into.
+fn walk(
+    root: &Path,
+    dir: &Path,
+    ignore: &[String],
+    out: &mut BTreeMap<String, Entry>,
+) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?;
+    for dir_entry in entries {
+        let dir_entry =
+            dir_entry.map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?;
+        let path = dir_entry.path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if is_ignored(&relative, ignore) {
+            continue;
+        }
+
+        let file_type = dir_entry
+            .file_type()
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        if file_type.is_dir() {
+            walk(root, &path, ignore, out)?;
+        } else {
+            out.insert(relative, entry(path)?);
+        }
+    }
+    Ok(())
+}
+
+fn entry(path: PathBuf) -> Result<Entry, String> {
+    let meta = fs::symlink_metadata(&path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let mode = if meta.file_type().is_symlink() {
+        FileMode::Link
+    } else if is_executable(&meta) {
+        FileMode::BlobExecutable
+    } else {
+        FileMode::Blob
+    };
+    Ok(Entry { path, mode })
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Symlinks are compared by their target, as git stores them. Files over
+/// the size limit are only read far enough to sniff for binary content.
+fn read_side(entry: &Entry, limits: &DiffLimits) -> Result<Side, String> {
+    let read_error =
+        |e: std::io::Error| format!("Failed to read '{}': {}", entry.path.display(), e);
+    let (content, size) = if entry.mode == FileMode::Link {
+        let target = fs::read_link(&entry.path)
+            .map(|target| target.to_string_lossy().into_owned().into_bytes())
+            .map_err(read_error)?;
+        let size = target.len() as u64;
+        (target, size)
+    } else {
+        let size = fs::metadata(&entry.path).map_err(read_error)?.len();
+        if git::over_size_limit(limits, [Some(size), None]) {
+            let mut head = Vec::new();
+            fs::File::open(&entry.path)
+                .and_then(|file| {
+                    file.take(encoding::BINARY_CHECK_LEN as u64)
+                        .read_to_end(&mut head)
+                })
+                .map_err(read_error)?;
+            (head, size)
+        } else {
+            let content = fs::read(&entry.path).map_err(read_error)?;
+            let size = content.len() as u64;
+            (content, size)
+        }
+    };
+    Ok(Side {
+        path: entry.path.clone(),
+        mode: entry.mode,
+        content,
+        size,
+    })
+}
+
+/// Whether both sides hold the same bytes, streaming from disk when either
+/// was only partly read.
+fn same_content(old: &Side, new: &Side) -> bool {
+    if !old.is_partial() && !new.is_partial() {
+        return old.content == new.content;
+    }
+    // Equal sizes mean both are over the limit, so both heads were read.
+    if old.size != new.size || old.content != new.content {
+        return false;
+    }
+    let open = |side: &Side| fs::File::open(&side.path).map(std::io::BufReader::new);
+    let (Ok(mut old_file), Ok(mut new_file)) = (open(old), open(new)) else {
+        return false;
+    };
+    let (mut old_buf, mut new_buf) = ([0; 8192], [0; 8192]);
+    loop {
+        let Ok(n) = old_file.read(&mut old_buf) else {
+            return false;
+        };
+        if n == 0 {
+            return new_file.read(&mut new_buf).is_ok_and(|m| m == 0);
+        }
+        if new_file.read_exact(&mut new_buf[..n]).is_err() || old_buf[..n] != new_buf[..n] {
+            return false;
+        }
+    }
+}
+
+/// Diff one path's two sides. `None` when they're identical.
+fn diff_file(
+    path: &str,
+    renamed_from: Option<&str>,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    settings: &DiffSettings,
+    lines_before: usize,
+) -> Option<DiffFile> {
+    let status = match (old, new) {
+        (None, None) => return None,
+        (None, Some(_)) => FileStatus::Added,
+        (Some(_), None) => FileStatus::Deleted,
+        (Some(old), Some(new)) => {
+            if renamed_from.is_none() && old.mode == new.mode && same_content(old, new) {
+                return None;
+            }
+            if renamed_from.is_some() {
+                FileStatus::Renamed
+            } else {
+                FileStatus::Modified
+            }
+        }
+    };
+    let old_path = renamed_from.unwrap_or(path);
+    let oid = |side: &Side| {
+        if side.is_partial() {
+            return None;
+        }
+        git2::Oid::hash_object(git2::ObjectType::Blob, &side.content)
+            .ok()
+            .map(|oid| oid.to_string())
+    };
+
+    let head = new.or(old).map_or(&[][..], |side| {
+        &side.content[..side.content.len().min(git::SNIFF_LEN)]
+    });
+    let binary = [old, new]
+        .iter()
+        .flatten()
+        .any(|side| encoding::is_binary(&side.content))
+        && !encoding::is_utf16(head, None);
+    let size = |side: Option<&Side>| side.map(|side| side.size);
+    let too_large = git::over_size_limit(&settings.limits, [size(old), size(new)]);
+
+    let text = if binary || too_large {
+        git::FileText::default()
+    } else {
+        git::decoded_text(
+            old.map(|side| (old_path, side.content.as_slice())),
+            new.map(|side| (path, side.content.as_slice())),
+            None,
+            settings,
+            lines_before,
+        )
+        .unwrap_or_default()
+    };
+    let mut changes = git::text_changes(text.hunks, text.additions, text.deletions, text.truncated);
+    if too_large {
+        changes.truncated.push(TruncationReason::FileSize);
+    }
+
+    Some(DiffFile {
+        path: path.to_string(),
+        status,
+        old_path: renamed_from.map(str::to_string),
+        similarity: None,
+        old_mode: old.map(|side| side.mode),
+        new_mode: new.map(|side| side.mode),
+        old_oid: old.and_then(oid),
+        new_oid: new.and_then(oid),
+        old_size: old.map(|side| side.size),
+        new_size: new.map(|side| side.size),
+        binary,
+        mime_type: mime::sniff(path, binary.then_some(head)),
+        encoding: text.encoding.map(|e| e.name().to_string()),
+        old_encoding: text
+            .old_encoding
+            .filter(|old| Some(*old) != text.encoding)
+            .map(|e| e.name().to_string()),
+        lossy: text.lossy,
+        eof_newline: changes.eof_newline,
+        line_endings: changes.line_endings,
+        additions: changes.additions,
+        deletions: changes.deletions,
+        hunks: changes.hunks,
+        submodule: None,
+        lfs: None,
+        truncated: changes.truncated,
+    })
+}
+
+/// Whether `path` matches one of the ignore globs. As in `.gitignore`, a glob
+/// without a `/` matches a file or directory name at any depth, while one
+/// with a `/` matches the whole path from the root. `*` and `?` stay within
+/// one path component; `**` spans any number of them.
+fn is_ignored(path: &str, globs: &[String]) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    globs.iter().any(|glob| {
+        let glob = glob.trim().trim_end_matches('/');
+        if glob.is_empty() {
+            false
+        } else if glob.contains('/') {
+            glob_match(glob.trim_start_matches('/').as_bytes(), path.as_bytes())
+        } else {
+            glob_match(glob.as_bytes(), name.as_bytes())
+        }
+    })
+}
+
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len())
+                .filter(|&i| i == 0 || i == text.len() || text[i - 1] == b'/')
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(rest, &text[i..])),
+        [b'?', rest @ ..] => {
+            text.first().is_some_and(|&c| c != b'/') && glob_match(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_compare_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old");
+        let new = dir.path().join("new");
+        write(&old, "same.txt", b"unchanged\n");
+        write(&new, "same.txt", b"unchanged\n");
+        write(&old, "src/main.rs", b"fn main() {\n    run();\n}\n");
+        write(&new, "src/main.rs", b"fn main() {\n    run(true);\n}\n");
+        write(&old, "removed.txt", b"gone\n");
+        write(&new, "docs/added.md", b"# New\n");
+        write(&old, "logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        write(&new, "logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDX");
+        write(&new, "node_modules/pkg/index.js", b"ignored\n");
+        write(&new, "build.log", b"ignored\n");
+        write(&new, "src/gen/out.rs", b"ignored\n");
+
+        let ignore = [
+            "node_modules".to_string(),
+            "*.log".into(),
+            "src/**/out.rs".into(),
+        ];
+        let result = compare_paths(
+            old.to_str().unwrap(),
+            new.to_str().unwrap(),
+            &ignore,
+            &DiffSettings::default(),
+        )
+        .unwrap();
+
+        let paths: Vec<&str> = result.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["docs/added.md", "logo.png", "removed.txt", "src/main.rs"]
+        );
+
+        let added = &result.files[0];
+        assert!(matches!(added.status, FileStatus::Added));
+        assert_eq!(added.additions, 1);
+        assert!(added.old_oid.is_none());
+
+        let logo = &result.files[1];
+        assert!(logo.binary && logo.hunks.is_empty());
+        assert_eq!(logo.mime_type.as_deref(), Some("image/png"));
+
+        assert!(matches!(result.files[2].status, FileStatus::Deleted));
+
+        let main = &result.files[3];
+        assert!(matches!(main.status, FileStatus::Modified));
+        assert_eq!((main.additions, main.deletions), (1, 1));
+        assert_eq!(main.encoding.as_deref(), Some("UTF-8"));
+        // Blob OIDs match what git would record for the same content.
+        assert_eq!(
+            main.old_oid,
+            git2::Oid::hash_object(git2::ObjectType::Blob, b"fn main() {\n    run();\n}\n")
+                .ok()
+                .map(|oid| oid.to_string())
+        );
+        assert_eq!(result.stats.additions, 2);
+    }
+
+    #[test]
+    fn test_compare_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a/config.ini", b"port = 80\nhost = a\n");
+        write(dir.path(), "b/config.ini", b"port = 8080\nhost = a\n");
+        write(dir.path(), "b/config.prod.ini", b"port = 80\nhost = a\n");
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        let settings = DiffSettings::default();
+
+        let result =
+            compare_paths(&path("a/config.ini"), &path("b/config.ini"), &[], &settings).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.files[0].path, "config.ini");
+        assert!(matches!(result.files[0].status, FileStatus::Modified));
+
+        let renamed = compare_paths(
+            &path("a/config.ini"),
+            &path("b/config.prod.ini"),
+            &[],
+            &settings,
+        )
+        .unwrap();
+        let file = &renamed.files[0];
+        assert!(matches!(file.status, FileStatus::Renamed));
+        assert_eq!(file.old_path.as_deref(), Some("config.ini"));
+        assert!(file.hunks.is_empty());
+
+        assert!(compare_paths(&path("a"), &path("b/config.ini"), &[], &settings).is_err());
+    }
+
+    #[test]
+    fn test_compare_dirs_skips_reading_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old");
+        let new = dir.path().join("new");
+        let big = "line\n".repeat(10_000);
+        write(&old, "same.log", big.as_bytes());
+        write(&new, "same.log", big.as_bytes());
+        write(&old, "grown.log", big.as_bytes());
+        write(&new, "grown.log", format!("{}tail\n", big).as_bytes());
+        let settings = DiffSettings {
+            limits: DiffLimits {
+                max_file_size: 1000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let side = read_side(&entry(new.join("grown.log")).unwrap(), &settings.limits).unwrap();
+        assert_eq!(side.size, big.len() as u64 + 5);
+        assert_eq!(side.content.len(), encoding::BINARY_CHECK_LEN);
+
+        let result =
+            compare_paths(old.to_str().unwrap(), new.to_str().unwrap(), &[], &settings).unwrap();
+        assert_eq!(result.files.len(), 1);
+        let grown = &result.files[0];
+        assert_eq!(grown.path, "grown.log");
+        assert_eq!(grown.truncated, vec![TruncationReason::FileSize]);
+        assert_eq!(grown.new_size, Some(big.len() as u64 + 5));
+        assert!(grown.new_oid.is_none() && grown.hunks.is_empty());
+    }
+
+    #[test]
+    fn test_ignore_globs() {
+        let globs = |g: &[&str]| g.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_ignored("a/b/target", &globs(&["target/"])));
+        assert!(is_ignored("x.min.js", &globs(&["*.min.js"])));
+        assert!(!is_ignored("src/x.js", &globs(&["src/*.ts"])));
+        assert!(is_ignored("src/deep/x.ts", &globs(&["src/**/*.ts"])));
+        assert!(is_ignored("src/x.ts", &globs(&["src/**/*.ts"])));
+        assert!(is_ignored("out/a/b", &globs(&["out/**"])));
+        assert!(!is_ignored("src/out", &globs(&["/out"])));
+        assert!(is_ignored("file1.txt", &globs(&["file?.txt"])));
+    }
+}
@@ -0,0 +1,89 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// Text decoded to UTF-8 for display.
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    /// Some bytes weren't valid in `encoding` and were replaced with U+FFFD.
+    pub lossy: bool,
+}
+
+/// Pick the encoding of `bytes`. A BOM wins, then valid UTF-8, then the
+/// `working-tree-encoding` attribute, then a statistical guess.
+///
+/// UTF-8 is checked before the attribute because blobs of files with a
+/// `working-tree-encoding` are stored as UTF-8; only the checked-out copy is
+/// in the declared encoding.
+pub fn detect(bytes: &[u8], declared: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    if let Some(encoding) = declared.and_then(|label| Encoding::for_label(label.as_bytes())) {
+        return encoding;
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+pub fn decode(bytes: &[u8], declared: Option<&str>) -> Decoded {
+    let encoding = detect(bytes, declared);
+    let (text, lossy) = encoding.decode_with_bom_removal(bytes);
+    Decoded {
+        text: text.into_owned(),
+        encoding,
+        lossy,
+    }
+}
+
+/// How much of a file [`is_binary`] looks at.
+pub const BINARY_CHECK_LEN: usize = 8000;
+
+/// git's heuristic: content with a NUL in its first 8000 bytes is binary.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// UTF-16 text is full of NULs, so libgit2 flags it as binary. Worth a second
+/// look when it carries a BOM or is declared in `.gitattributes`.
+pub fn is_utf16(bytes: &[u8], declared: Option<&str>) -> bool {
+    let encoding = Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| declared.and_then(|label| Encoding::for_label(label.as_bytes())));
+    encoding == Some(UTF_16LE) || encoding == Some(UTF_16BE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_bom_and_utf8() {
+        let decoded = decode(b"\xef\xbb\xbfcaf\xc3\xa9\n", None);
+        assert_eq!(decoded.encoding, UTF_8);
+        assert_eq!(decoded.text, "café\n");
+        assert!(!decoded.lossy);
+
+        let utf16: Vec<u8> = [0xff, 0xfe, b'h', 0, b'i', 0].to_vec();
+        assert!(is_utf16(&utf16, None));
+        assert_eq!(decode(&utf16, None).text, "hi");
+    }
+
+    #[test]
+    fn test_decode_declared_and_guessed() {
+        // "ｱｲｳ" in Shift_JIS, declared via working-tree-encoding.
+        let sjis = b"\xb1\xb2\xb3\n";
+        let decoded = decode(sjis, Some("Shift_JIS"));
+        assert_eq!(decoded.encoding.name(), "Shift_JIS");
+        assert_eq!(decoded.text, "ｱｲｳ\n");
+
+        let latin1 = b"Gr\xf6\xdfe \xfcber alles, sagte der B\xe4r\n";
+        let decoded = decode(latin1, None);
+        assert_eq!(decoded.text, "Größe über alles, sagte der Bär\n");
+        assert!(!decoded.lossy);
+    }
+}
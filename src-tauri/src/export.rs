@@ -0,0 +1,138 @@
+use git2::Repository;
+
+use crate::curate;
+use crate::git;
+use crate::types::{DiffParams, DiffSide, FileSelection};
+
+/// The base..compare diff as a unified patch that `git apply` accepts,
+/// optionally limited to the selected files, hunks and lines.
+pub fn unified_patch(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+    selection: Option<&[FileSelection]>,
+) -> Result<Vec<u8>, String> {
+    let Some(selection) = selection else {
+        return Ok(git::diff_patch(repo, base, compare, params, &[])?.text);
+    };
+    let paths: Vec<&str> = selection
+        .iter()
+        .flat_map(|f| std::iter::once(f.path.as_str()).chain(f.old_path.as_deref()))
+        .collect();
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let patch = git::diff_patch(repo, base, compare, params, &paths)?;
+    curate::select_changes(&patch.text, selection)
+}
+
+/// The commits in base..compare as a `git format-patch --stdout` style mbox,
+/// one message per commit, oldest first. Merge commits are skipped, as
+/// format-patch does.
+pub fn format_patch(repo: &Repository, base: &str, compare: &str) -> Result<Vec<u8>, String> {
+    let base_oid = git::resolve_commit(repo, base, DiffSide::Base)?.id();
+    let compare_oid = git::compare_head(repo, compare)?;
+    let commits: Vec<_> = git::commits_between(repo, base_oid, compare_oid)?
+        .into_iter()
+        .filter(|c| c.parent_count() <= 1)
+        .collect();
+
+    let mut mbox = Vec::new();
+    for (idx, commit) in commits.iter().enumerate() {
+        let tree = commit
+            .tree()
+            .map_err(|e| format!("Failed to get tree for {}: {}", commit.id(), e))?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(
+                parent
+                    .tree()
+                    .map_err(|e| format!("Failed to get tree for {}: {}", parent.id(), e))?,
+            ),
+            Err(_) => None,
+        };
+        let mut opts = git2::DiffOptions::new();
+        opts.show_binary(true);
+        let diff = repo
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut opts))
+            .and_then(|mut diff| diff.find_similar(None).map(|_| diff))
+            .map_err(|e| format!("Failed to diff {}: {}", commit.id(), e))?;
+
+        let email = git2::Email::from_diff(
+            &diff,
+            idx + 1,
+            commits.len(),
+            &commit.id(),
+            commit.summary().unwrap_or(""),
+            commit.body().unwrap_or(""),
+            &commit.author(),
+            &mut git2::EmailCreateOptions::new(),
+        )
+        .map_err(|e| format!("Failed to format {}: {}", commit.id(), e))?;
+        mbox.extend_from_slice(email.as_slice());
+    }
+    Ok(mbox)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_support::commit;
+    use crate::types::HunkSelection;
+
+    fn setup() -> (tempfile::TempDir, Repository, git2::Oid, git2::Oid) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, None, &[], "base");
+        let a = commit(
+            &repo,
+            Some(base),
+            &[("a.txt", Some("alpha\n"))],
+            "Add a\n\nWith a body.",
+        );
+        let tip = commit(&repo, Some(a), &[("b.txt", Some("beta\n"))], "Add b");
+        (dir, repo, base, tip)
+    }
+
+    #[test]
+    fn test_unified_patch_full_and_selected() {
+        let (_dir, repo, base, tip) = setup();
+        let params = DiffParams::default();
+        let (base, tip) = (base.to_string(), tip.to_string());
+
+        let full =
+            String::from_utf8(unified_patch(&repo, &base, &tip, &params, None).unwrap()).unwrap();
+        assert!(full.contains("diff --git a/a.txt b/a.txt\n"));
+        assert!(full.contains("+++ b/b.txt\n@@ -0,0 +1 @@\n+beta\n"));
+
+        let selection = [FileSelection {
+            path: "b.txt".into(),
+            old_path: None,
+            hunks: Some(vec![HunkSelection {
+                index: 0,
+                lines: None,
+            }]),
+        }];
+        let only_b = String::from_utf8(
+            unified_patch(&repo, &base, &tip, &params, Some(&selection)).unwrap(),
+        )
+        .unwrap();
+        assert!(only_b.starts_with("diff --git a/b.txt b/b.txt\n"));
+        assert!(!only_b.contains("a.txt"));
+        git2::Diff::from_buffer(only_b.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_format_patch_numbers_each_commit() {
+        let (_dir, repo, base, tip) = setup();
+        let mbox =
+            String::from_utf8(format_patch(&repo, &base.to_string(), &tip.to_string()).unwrap())
+                .unwrap();
+
+        assert_eq!(mbox.matches("\nFrom: Test <test@test.com>\n").count(), 2);
+        assert!(mbox.contains("Subject: [PATCH 1/2] Add a\n"));
+        assert!(mbox.contains("Subject: [PATCH 2/2] Add b\n"));
+        assert!(mbox.contains("With a body."));
+        assert!(mbox.contains("+++ b/b.txt\n"));
+    }
+}
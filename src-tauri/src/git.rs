@@ -1,6 +1,29 @@
-use git2::Repository;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
-use crate::types::{DiffFile, DiffHunk, DiffLine, DiffResult, FileStatus, LineType, RefInfo, RefType};
+use base64::Engine;
+use git2::{ErrorCode, Repository};
+
+use crate::cache::{DiffCache, DiffKey};
+use crate::encoding;
+use crate::intraline;
+use crate::lfs;
+use crate::mime;
+use crate::stats;
+use crate::types::{
+    BlobContent, BlobPair, CommitInfo, CompareMode, DiffAlgorithm, DiffFile, DiffHunk, DiffLimits,
+    DiffLine, DiffParams, DiffResult, DiffSettings, DiffSide, DiffSummary, EofNewline, FileLines,
+    FileMode, FileStatus, FileSummary, LfsPointer, LineEnding, LineEndingChange, LineType, RefInfo,
+    RefType, RenameSettings, SubmoduleChange, SubmoduleCommit, TruncationReason, WorktreeInfo,
+};
+
+/// Prefix marking a compare spec as a linked worktree (`worktree:<name>`)
+/// rather than a revspec. `:` can't appear in a ref name, so no branch or tag
+/// is shadowed, but `worktree:<path>` is also valid `<rev>:<path>` syntax for
+/// a blob in a ref named `worktree`. Specs are checked for the prefix before
+/// they reach revparse, so the worktree reading always wins.
+pub const WORKTREE_PREFIX: &str = "worktree:";
 
 pub fn discover_repo(path: &str) -> Result<Repository, String> {
     Repository::discover(path).map_err(|e| format!("Failed to discover repo: {}", e))
@@ -23,6 +46,7 @@ pub fn list_refs(repo: &Repository) -> Result<Vec<RefInfo>, String> {
             refs.push(RefInfo {
                 name: name.to_string(),
                 ref_type: RefType::Branch,
+                worktree: None,
             });
         }
     }
@@ -36,122 +60,1683 @@ pub fn list_refs(repo: &Repository) -> Result<Vec<RefInfo>, String> {
         refs.push(RefInfo {
             name: tag_name.to_string(),
             ref_type: RefType::Tag,
+            worktree: None,
+        });
+    }
+
+    // Linked worktrees
+    let worktree_names = repo
+        .worktrees()
+        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
+
+    for wt_name in worktree_names.iter().flatten() {
+        let worktree = repo
+            .find_worktree(wt_name)
+            .map_err(|e| format!("Failed to open worktree '{}': {}", wt_name, e))?;
+        refs.push(RefInfo {
+            name: format!("{}{}", WORKTREE_PREFIX, wt_name),
+            ref_type: RefType::Worktree,
+            worktree: Some(worktree_info(&worktree)),
         });
     }
 
     Ok(refs)
 }
 
-pub fn generate_diff(repo: &Repository, base: &str, compare: &str) -> Result<DiffResult, String> {
-    let base_ref = format!("refs/heads/{}", base);
-    let compare_ref = format!("refs/heads/{}", compare);
+fn worktree_info(worktree: &git2::Worktree) -> WorktreeInfo {
+    let (locked, lock_reason) = match worktree.is_locked() {
+        Ok(git2::WorktreeLockStatus::Locked(reason)) => (true, reason),
+        _ => (false, None),
+    };
+
+    // A prunable worktree has lost its working directory, so there is no
+    // HEAD to read a branch from.
+    let branch = Repository::open_from_worktree(worktree)
+        .ok()
+        .and_then(|wt_repo| {
+            let head = wt_repo.head().ok()?;
+            if head.is_branch() {
+                head.shorthand().map(str::to_string)
+            } else {
+                None
+            }
+        });
+
+    WorktreeInfo {
+        path: worktree.path().to_string_lossy().to_string(),
+        branch,
+        locked,
+        lock_reason,
+        prunable: worktree.is_prunable(None).unwrap_or(false),
+    }
+}
+
+fn open_worktree(repo: &Repository, name: &str) -> Result<Repository, String> {
+    let worktree = repo
+        .find_worktree(name)
+        .map_err(|e| format!("Failed to find worktree '{}': {}", name, e))?;
+    Repository::open_from_worktree(&worktree)
+        .map_err(|e| format!("Failed to open worktree '{}': {}", name, e))
+}
+
+/// The checkout that has `branch` checked out: the repository itself or one
+/// of its linked worktrees. `None` if it isn't checked out anywhere.
+pub fn branch_checkout(repo: &Repository, branch: &str) -> Result<Option<Repository>, String> {
+    let has_branch = |checkout: &Repository| {
+        checkout.workdir().is_some()
+            && checkout.head().is_ok_and(|head| {
+                head.is_branch()
+                    && (head.shorthand() == Some(branch) || head.name() == Some(branch))
+            })
+    };
+    if has_branch(repo) {
+        return Repository::open(repo.path())
+            .map(Some)
+            .map_err(|e| format!("Failed to open repository: {}", e));
+    }
+
+    let names = repo
+        .worktrees()
+        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
+    for name in names.iter().flatten() {
+        if let Ok(checkout) = open_worktree(repo, name) {
+            if has_branch(&checkout) {
+                return Ok(Some(checkout));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveErrorKind {
+    /// No ref or object matches the spec.
+    NotFound,
+    /// An abbreviated OID matches more than one object.
+    Ambiguous,
+    /// The spec is not valid revision syntax.
+    InvalidSpec,
+    /// The spec resolves, but not to something that peels to a commit.
+    NotACommit,
+    Other,
+}
+
+/// What a revspec was given as, for error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecRole {
+    Base,
+    Compare,
+    /// The first commit of a [`CommitRange`](crate::types::CommitRange).
+    RangeFrom,
+    /// The last commit of a [`CommitRange`](crate::types::CommitRange).
+    RangeTo,
+    /// A recorded review round.
+    Round,
+}
+
+impl From<DiffSide> for SpecRole {
+    fn from(side: DiffSide) -> Self {
+        match side {
+            DiffSide::Base => SpecRole::Base,
+            DiffSide::Compare => SpecRole::Compare,
+        }
+    }
+}
+
+/// A revspec that could not be turned into a commit, tagged with what it was
+/// given as.
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    pub role: SpecRole,
+    pub spec: String,
+    pub kind: ResolveErrorKind,
+    pub message: String,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let role = match self.role {
+            SpecRole::Base => "base",
+            SpecRole::Compare => "compare",
+            SpecRole::RangeFrom => "commit range start",
+            SpecRole::RangeTo => "commit range end",
+            SpecRole::Round => "review round",
+        };
+        let reason = match self.kind {
+            ResolveErrorKind::NotFound => "no matching ref or commit",
+            ResolveErrorKind::Ambiguous => "ambiguous object name",
+            ResolveErrorKind::InvalidSpec => "invalid revision syntax",
+            ResolveErrorKind::NotACommit => "does not point to a commit",
+            ResolveErrorKind::Other => "resolution failed",
+        };
+        write!(
+            f,
+            "Failed to resolve {} '{}': {} ({})",
+            role, self.spec, reason, self.message
+        )
+    }
+}
+
+impl From<ResolveError> for String {
+    fn from(e: ResolveError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Resolve any revspec git understands (branch, tag, remote-tracking ref,
+/// abbreviated OID, `HEAD~3`, `stash@{0}`, ...) to a commit.
+pub fn resolve_commit<'r>(
+    repo: &'r Repository,
+    spec: &str,
+    role: impl Into<SpecRole>,
+) -> Result<git2::Commit<'r>, ResolveError> {
+    let role = role.into();
+    let error = |kind: ResolveErrorKind, e: git2::Error| ResolveError {
+        role,
+        spec: spec.to_string(),
+        kind,
+        message: e.message().to_string(),
+    };
+
+    let obj = repo.revparse_single(spec).map_err(|e| {
+        let kind = match e.code() {
+            ErrorCode::NotFound => ResolveErrorKind::NotFound,
+            ErrorCode::Ambiguous => ResolveErrorKind::Ambiguous,
+            ErrorCode::InvalidSpec => ResolveErrorKind::InvalidSpec,
+            _ => ResolveErrorKind::Other,
+        };
+        error(kind, e)
+    })?;
+
+    obj.peel_to_commit()
+        .map_err(|e| error(ResolveErrorKind::NotACommit, e))
+}
+
+/// Diff `base` against `compare`.
+///
+/// In [`CompareMode::Tree`] `compare` is a revspec. The other modes diff a
+/// live checkout instead: `compare` may name a linked worktree
+/// (`worktree:<name>`), otherwise the opened repository's own working
+/// directory is used, and `compare` must be empty or name the commit checked
+/// out there. A worktree compare target always reads the checkout, so `Tree`
+/// is treated as `Workdir` for it.
+///
+/// With `params.merge_base` set the diff starts from the merge-base of base and
+/// compare rather than base itself, so commits that landed on base after
+/// compare branched off don't show up as reverted changes.
+pub fn generate_diff(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+) -> Result<DiffResult, String> {
+    let sides = resolve_sides(repo, base, compare, params)?;
+    let (ahead, behind) = sides.ahead_behind(repo)?;
+    let checkout = sides.checkout(repo);
+    let diff = sides_diff(checkout, &sides, params, &[])?;
+    let files = diff_files(checkout, &diff, &params.settings, 0)?;
+    let stats = stats::diff_stats(
+        files
+            .iter()
+            .map(|f| (f.path.as_str(), f.additions, f.deletions)),
+    );
+
+    Ok(DiffResult {
+        base_ref: base.to_string(),
+        compare_ref: compare.to_string(),
+        base_oid: sides.base_commit.to_string(),
+        compare_oid: sides.compare_commit.to_string(),
+        mode: params.mode,
+        merge_base: sides.merge_base.map(|oid| oid.to_string()),
+        ahead,
+        behind,
+        stats,
+        files,
+    })
+}
+
+/// Like [`generate_diff`], but only lists the changed files with their line
+/// counts. Hunks are loaded per file with [`file_diff`].
+pub fn diff_summary(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+    cache: &mut DiffCache,
+) -> Result<DiffSummary, String> {
+    let sides = resolve_sides(repo, base, compare, params)?;
+    let (ahead, behind) = sides.ahead_behind(repo)?;
+    let mut cached = sides.key(params).map(|key| cache.entry(key));
+
+    let files = match cached.as_ref().and_then(|c| c.files.clone()) {
+        Some(files) => files,
+        None => {
+            let checkout = sides.checkout(repo);
+            let diff = sides_diff(checkout, &sides, params, &[])?;
+            let files = file_summaries(checkout, &diff, &params.settings)?;
+            if let Some(cached) = cached.as_mut() {
+                cached.files = Some(files.clone());
+            }
+            files
+        }
+    };
+    let stats = stats::diff_stats(
+        files
+            .iter()
+            .map(|f| (f.path.as_str(), f.additions, f.deletions)),
+    );
+
+    Ok(DiffSummary {
+        base_ref: base.to_string(),
+        compare_ref: compare.to_string(),
+        base_oid: sides.base_commit.to_string(),
+        compare_oid: sides.compare_commit.to_string(),
+        mode: params.mode,
+        merge_base: sides.merge_base.map(|oid| oid.to_string()),
+        ahead,
+        behind,
+        stats,
+        files,
+    })
+}
+
+/// Hunks and content details for a single file of the diff. Pass the file's
+/// `old_path` for renames and copies so both paths take part in detection,
+/// and the changed lines of the files listed before it in the summary as
+/// `lines_before`, so `max_total_lines` holds across the whole diff.
+#[allow(clippy::too_many_arguments)]
+pub fn file_diff(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+    path: &str,
+    old_path: Option<&str>,
+    lines_before: usize,
+    cache: &mut DiffCache,
+) -> Result<DiffFile, String> {
+    let sides = resolve_sides(repo, base, compare, params)?;
+    let mut cached = sides.key(params).map(|key| cache.entry(key));
+    // Earlier files only matter when there's a total-lines budget to spend.
+    let budget_spent = if params.settings.limits.max_total_lines == 0 {
+        0
+    } else {
+        lines_before
+    };
+    let file_key = (path.to_string(), budget_spent);
+    if let Some(file) = cached.as_ref().and_then(|c| c.file_diffs.get(&file_key)) {
+        return Ok(file.clone());
+    }
+
+    let checkout = sides.checkout(repo);
+    let paths: Vec<&str> = std::iter::once(path).chain(old_path).collect();
+    let diff = sides_diff(checkout, &sides, params, &paths)?;
+    let file = diff_files(checkout, &diff, &params.settings, lines_before)?
+        .into_iter()
+        .find(|f| f.path == path)
+        .ok_or_else(|| format!("'{}' has no changes in this diff", path))?;
+
+    if let Some(cached) = cached.as_mut() {
+        cached.file_diffs.insert(file_key, file.clone());
+    }
+    Ok(file)
+}
+
+/// A diff printed as `git diff --binary` would, so it can be edited and
+/// handed to libgit2's apply machinery.
+pub struct DiffPatch {
+    /// The commit the diff starts from; `None` for the empty tree.
+    pub from_commit: Option<git2::Oid>,
+    pub from_tree: git2::Oid,
+    pub text: Vec<u8>,
+}
+
+/// The diff [`generate_diff`] shows, as patch text. Limited to `paths` if any
+/// are given; pass both sides of renames and copies.
+pub fn diff_patch(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+    paths: &[&str],
+) -> Result<DiffPatch, String> {
+    let sides = resolve_sides(repo, base, compare, params)?;
+    let mut opts = path_options(&params.settings, paths);
+    opts.show_binary(true);
+    let diff = sides_diff_with(sides.checkout(repo), &sides, params, opts)?;
+
+    let mut text = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        // File and hunk headers carry their own text; only content lines
+        // need their origin marker put back.
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            text.push(line.origin() as u8);
+        }
+        text.extend_from_slice(line.content());
+        true
+    })
+    .map_err(|e| format!("Failed to format patch: {}", e))?;
+
+    Ok(DiffPatch {
+        from_commit: sides.from_commit,
+        from_tree: sides.from_tree,
+        text,
+    })
+}
+
+/// Both sides of a comparison, resolved to commits and trees.
+struct Sides {
+    /// The linked worktree named by a `worktree:<name>` compare spec.
+    worktree: Option<Repository>,
+    base_commit: git2::Oid,
+    compare_commit: git2::Oid,
+    merge_base: Option<git2::Oid>,
+    /// The commit `from_tree` belongs to; `None` for the empty tree.
+    from_commit: Option<git2::Oid>,
+    /// Where the diff starts: base's tree, or the merge-base's.
+    from_tree: git2::Oid,
+    /// `None` when compare is read from a checkout's working directory or
+    /// index rather than a commit.
+    compare_tree: Option<git2::Oid>,
+}
+
+impl Sides {
+    /// The repository the compare side lives in.
+    fn checkout<'a>(&'a self, repo: &'a Repository) -> &'a Repository {
+        self.worktree.as_ref().unwrap_or(repo)
+    }
+
+    fn ahead_behind(&self, repo: &Repository) -> Result<(usize, usize), String> {
+        repo.graph_ahead_behind(self.compare_commit, self.base_commit)
+            .map_err(|e| format!("Failed to count commits between refs: {}", e))
+    }
+
+    /// Cache key for tree-to-tree diffs; checkouts can change at any time.
+    fn key(&self, params: &DiffParams) -> Option<DiffKey> {
+        self.compare_tree.map(|to_tree| DiffKey {
+            from_tree: self.from_tree,
+            to_tree,
+            settings: params.settings.clone(),
+        })
+    }
+}
+
+fn resolve_sides(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    params: &DiffParams,
+) -> Result<Sides, String> {
+    let base_commit = resolve_commit(repo, base, DiffSide::Base)?;
+
+    if let Some(range) = &params.commits {
+        let from = resolve_commit(repo, &range.from, SpecRole::RangeFrom)?;
+        let to = resolve_commit(repo, &range.to, SpecRole::RangeTo)?;
+        let reachable = from.id() == to.id()
+            || repo
+                .graph_descendant_of(to.id(), from.id())
+                .map_err(|e| format!("Failed to check commit range: {}", e))?;
+        if !reachable {
+            return Err(format!(
+                "Commit range start '{}' isn't an ancestor of '{}'",
+                range.from, range.to
+            ));
+        }
+        let parent = from.parent(0).ok();
+        let from_tree = match &parent {
+            Some(parent) => parent.tree_id(),
+            // A root commit is diffed against the empty tree.
+            None => repo
+                .treebuilder(None)
+                .and_then(|builder| builder.write())
+                .map_err(|e| format!("Failed to create empty tree: {}", e))?,
+        };
+        return Ok(Sides {
+            worktree: None,
+            base_commit: base_commit.id(),
+            compare_commit: to.id(),
+            merge_base: repo.merge_base(base_commit.id(), to.id()).ok(),
+            from_commit: parent.map(|parent| parent.id()),
+            from_tree,
+            compare_tree: Some(to.tree_id()),
+        });
+    }
+
+    let worktree = match compare.strip_prefix(WORKTREE_PREFIX) {
+        Some(name) => Some(open_worktree(repo, name)?),
+        None => None,
+    };
+    let from_checkout = worktree.is_some() || params.mode != CompareMode::Tree;
+    // Don't report a ref as compared when the checkout is what gets diffed.
+    if worktree.is_none() && from_checkout && !compare.is_empty() {
+        let named = resolve_commit(repo, compare, DiffSide::Compare)?.id();
+        if named != head_commit(repo)? {
+            return Err(format!(
+                "'{}' isn't checked out; working tree and index diffs compare the checkout, \
+                 so pick a worktree or leave compare empty",
+                compare
+            ));
+        }
+    }
+
+    let (compare_commit, compare_tree) = if from_checkout {
+        (head_commit(worktree.as_ref().unwrap_or(repo))?, None)
+    } else {
+        let commit = resolve_commit(repo, compare, DiffSide::Compare)?;
+        let tree = commit
+            .tree()
+            .map_err(|e| format!("Failed to get tree for '{}': {}", compare, e))?;
+        (commit.id(), Some(tree.id()))
+    };
+
+    let merge_base = repo.merge_base(base_commit.id(), compare_commit).ok();
+    let from_oid = if params.merge_base {
+        merge_base.ok_or_else(|| format!("'{}' and '{}' have no common ancestor", base, compare))?
+    } else {
+        base_commit.id()
+    };
+    let from_tree = repo
+        .find_commit(from_oid)
+        .and_then(|c| c.tree())
+        .map_err(|e| format!("Failed to get tree for '{}': {}", base, e))?
+        .id();
+
+    Ok(Sides {
+        worktree,
+        base_commit: base_commit.id(),
+        compare_commit,
+        merge_base,
+        from_commit: Some(from_oid),
+        from_tree,
+        compare_tree,
+    })
+}
+
+fn head_commit(repo: &Repository) -> Result<git2::Oid, String> {
+    repo.head()
+        .and_then(|h| h.peel_to_commit())
+        .map(|c| c.id())
+        .map_err(|e| format!("Failed to read HEAD: {}", e))
+}
+
+/// Commits reachable from `compare` but not `base`, oldest first, each with
+/// the files it touches relative to its first parent.
+pub fn list_commits(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+) -> Result<Vec<CommitInfo>, String> {
+    let base_commit = resolve_commit(repo, base, DiffSide::Base)?;
+    let compare_commit = compare_head(repo, compare)?;
+    commits_between(repo, base_commit.id(), compare_commit)?
+        .iter()
+        .map(|commit| commit_info(repo, commit))
+        .collect()
+}
+
+/// The commit a compare spec points at: a revspec, or the HEAD of a
+/// `worktree:<name>` checkout.
+pub fn compare_head(repo: &Repository, compare: &str) -> Result<git2::Oid, String> {
+    match compare.strip_prefix(WORKTREE_PREFIX) {
+        Some(name) => head_commit(&open_worktree(repo, name)?),
+        None => Ok(resolve_commit(repo, compare, DiffSide::Compare)?.id()),
+    }
+}
+
+/// Commits reachable from `tip` but not `hide`, oldest first.
+pub fn commits_between(
+    repo: &Repository,
+    hide: git2::Oid,
+    tip: git2::Oid,
+) -> Result<Vec<git2::Commit<'_>>, String> {
+    let mut walk = repo
+        .revwalk()
+        .map_err(|e| format!("Failed to walk history: {}", e))?;
+    walk.push(tip)
+        .and_then(|_| walk.hide(hide))
+        .and_then(|_| walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE))
+        .map_err(|e| format!("Failed to walk history: {}", e))?;
+
+    walk.map(|oid| {
+        oid.and_then(|oid| repo.find_commit(oid))
+            .map_err(|e| format!("Failed to read commit: {}", e))
+    })
+    .collect()
+}
+
+pub fn commit_info(repo: &Repository, commit: &git2::Commit) -> Result<CommitInfo, String> {
+    let parent_tree = commit.parent(0).and_then(|p| p.tree()).ok();
+    let files = commit
+        .tree()
+        .and_then(|tree| repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None))
+        .map_err(|e| format!("Failed to diff commit {}: {}", commit.id(), e))?
+        .deltas()
+        .map(|delta| delta_path(&delta))
+        .collect();
+
+    let author = commit.author();
+    Ok(CommitInfo {
+        oid: commit.id().to_string(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        author_email: author.email().unwrap_or_default().to_string(),
+        time: author.when().seconds(),
+        parents: commit.parent_ids().map(|id| id.to_string()).collect(),
+        files,
+    })
+}
+
+/// Diff the resolved sides with renames detected, limited to `paths` if any
+/// are given.
+fn sides_diff<'r>(
+    checkout: &'r Repository,
+    sides: &Sides,
+    params: &DiffParams,
+    paths: &[&str],
+) -> Result<git2::Diff<'r>, String> {
+    sides_diff_with(
+        checkout,
+        sides,
+        params,
+        path_options(&params.settings, paths),
+    )
+}
+
+fn path_options(settings: &DiffSettings, paths: &[&str]) -> git2::DiffOptions {
+    let mut opts = diff_options(settings);
+    if !paths.is_empty() {
+        opts.disable_pathspec_match(true);
+        for path in paths {
+            opts.pathspec(path);
+        }
+    }
+    opts
+}
+
+fn sides_diff_with<'r>(
+    checkout: &'r Repository,
+    sides: &Sides,
+    params: &DiffParams,
+    mut opts: git2::DiffOptions,
+) -> Result<git2::Diff<'r>, String> {
+    // Linked worktrees share the object database, so trees resolved in the
+    // main repository are reachable from the checkout's handle too.
+    let base_tree = checkout
+        .find_tree(sides.from_tree)
+        .map_err(|e| format!("Failed to load base tree: {}", e))?;
+
+    let mut diff = match sides.compare_tree {
+        None => checkout_diff(checkout, &base_tree, params.mode, opts)?,
+        Some(oid) => {
+            let compare_tree = checkout
+                .find_tree(oid)
+                .map_err(|e| format!("Failed to load compare tree: {}", e))?;
+            checkout
+                .diff_tree_to_tree(Some(&base_tree), Some(&compare_tree), Some(&mut opts))
+                .map_err(|e| format!("Failed to generate diff: {}", e))?
+        }
+    };
+    find_renames(&mut diff, &params.settings.renames)?;
+    Ok(diff)
+}
+
+/// Options shared by every diff we generate.
+fn diff_options(settings: &DiffSettings) -> git2::DiffOptions {
+    let mut opts = git2::DiffOptions::new();
+    // Without these libgit2 reports a kind change as a delete plus an add.
+    opts.include_typechange(true)
+        .include_typechange_trees(true)
+        .ignore_whitespace(settings.ignore_whitespace)
+        .ignore_whitespace_change(settings.ignore_whitespace_change)
+        .ignore_whitespace_eol(settings.ignore_whitespace_eol)
+        .context_lines(settings.context_lines)
+        .interhunk_lines(settings.interhunk_lines)
+        .minimal(settings.algorithm == DiffAlgorithm::Minimal)
+        .patience(settings.algorithm == DiffAlgorithm::Patience)
+        .indent_heuristic(settings.indent_heuristic);
+    opts
+}
+
+/// Diff a checkout's working directory or index against `base_tree`.
+fn checkout_diff<'r>(
+    repo: &'r Repository,
+    base_tree: &git2::Tree,
+    mode: CompareMode,
+    mut opts: git2::DiffOptions,
+) -> Result<git2::Diff<'r>, String> {
+    if repo.workdir().is_none() {
+        return Err("Repository has no working directory".into());
+    }
+
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+
+    match mode {
+        CompareMode::Tree | CompareMode::Workdir => {
+            repo.diff_tree_to_workdir_with_index(Some(base_tree), Some(&mut opts))
+        }
+        CompareMode::Index => repo.diff_tree_to_index(Some(base_tree), None, Some(&mut opts)),
+        CompareMode::WorkdirIndex => repo.diff_index_to_workdir(None, Some(&mut opts)),
+    }
+    .map_err(|e| format!("Failed to generate diff: {}", e))
+}
+
+/// Pair up deletions with additions (and, optionally, modified sources with
+/// additions) so moved and copied files keep their history.
+fn find_renames(diff: &mut git2::Diff, settings: &RenameSettings) -> Result<(), String> {
+    if !settings.renames && !settings.copies {
+        return Ok(());
+    }
+
+    let mut opts = git2::DiffFindOptions::new();
+    opts.renames(settings.renames)
+        .copies(settings.copies)
+        .for_untracked(true)
+        .rename_threshold(settings.threshold)
+        .copy_threshold(settings.threshold)
+        .rename_limit(settings.limit);
+
+    diff.find_similar(Some(&mut opts))
+        .map_err(|e| format!("Failed to detect renames: {}", e))
+}
+
+/// Similarity scores for renamed and copied deltas, keyed by new path.
+///
+/// git2 doesn't expose `git_diff_delta::similarity`, but libgit2 prints it
+/// as `similarity index N%` in the patch header.
+fn similarity_by_path(diff: &git2::Diff) -> Result<HashMap<String, u32>, String> {
+    let mut scores = HashMap::new();
+    diff.print(git2::DiffFormat::PatchHeader, |delta, _hunk, line| {
+        let header = String::from_utf8_lossy(line.content());
+        let score = header.lines().find_map(|l| {
+            l.strip_prefix("similarity index ")
+                .and_then(|rest| rest.trim_end_matches('%').parse::<u32>().ok())
+        });
+        if let (Some(score), Some(path)) = (score, delta.new_file().path()) {
+            scores.insert(path.to_string_lossy().to_string(), score);
+        }
+        true
+    })
+    .map_err(|e| format!("Failed to read diff headers: {}", e))?;
+    Ok(scores)
+}
+
+fn file_mode(mode: git2::FileMode) -> Option<FileMode> {
+    match mode {
+        git2::FileMode::Unreadable => None,
+        git2::FileMode::Blob | git2::FileMode::BlobGroupWritable => Some(FileMode::Blob),
+        git2::FileMode::BlobExecutable => Some(FileMode::BlobExecutable),
+        git2::FileMode::Link => Some(FileMode::Link),
+        git2::FileMode::Commit => Some(FileMode::Commit),
+        git2::FileMode::Tree => Some(FileMode::Tree),
+    }
+}
+
+/// Map raw mode bits, as found in index entries and patch headers.
+pub fn mode_from_bits(mode: u32) -> Option<FileMode> {
+    match mode {
+        0o100644 | 0o100664 => Some(FileMode::Blob),
+        0o100755 => Some(FileMode::BlobExecutable),
+        0o120000 => Some(FileMode::Link),
+        0o160000 => Some(FileMode::Commit),
+        0o040000 => Some(FileMode::Tree),
+        _ => None,
+    }
+}
+
+/// Bytes of the MIME sniffing window.
+pub const SNIFF_LEN: usize = 512;
+
+/// OID and size of one side of a delta. Working-directory files often come
+/// back from libgit2 without a computed OID, so hash those ourselves.
+fn side_info(repo: &Repository, file: &git2::DiffFile) -> (Option<String>, Option<u64>) {
+    if file_mode(file.mode()).is_none() {
+        return (None, None);
+    }
+    let oid = if file.is_valid_id() && !file.id().is_zero() {
+        Some(file.id())
+    } else {
+        file.path().zip(repo.workdir()).and_then(|(path, workdir)| {
+            git2::Oid::hash_file(git2::ObjectType::Blob, workdir.join(path)).ok()
+        })
+    };
+    (oid.map(|oid| oid.to_string()), Some(file.size()))
+}
+
+/// Content of one side of a delta: from the object database if it's there,
+/// otherwise from the working directory of `repo`.
+fn side_content(repo: &Repository, path: &str, oid: &str) -> Result<Vec<u8>, String> {
+    let oid = git2::Oid::from_str(oid).map_err(|e| format!("Invalid OID '{}': {}", oid, e))?;
+    if let Ok(blob) = repo.find_blob(oid) {
+        return Ok(lfs::resolve(repo, blob.content().to_vec()));
+    }
+
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| format!("Blob {} not found", oid))?;
+    let full_path = checkout_path(workdir, path)?;
+    // The file may have changed since the diff was taken; don't hand back
+    // content that doesn't match what was reviewed.
+    let on_disk = git2::Oid::hash_file(git2::ObjectType::Blob, &full_path)
+        .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    if on_disk != oid {
+        return Err(format!(
+            "'{}' changed on disk since the diff was loaded",
+            path
+        ));
+    }
+    std::fs::read(&full_path).map_err(|e| format!("Failed to read '{}': {}", path, e))
+}
+
+/// Both versions of a file for preview. `compare` picks the checkout used for
+/// new-side content that isn't in the object database yet.
+pub fn blob_pair(
+    repo: &Repository,
+    compare: &str,
+    path: &str,
+    old_path: Option<&str>,
+    old_oid: Option<&str>,
+    new_oid: Option<&str>,
+) -> Result<BlobPair, String> {
+    let wt_repo = match compare.strip_prefix(WORKTREE_PREFIX) {
+        Some(name) => Some(open_worktree(repo, name)?),
+        None => None,
+    };
+    let checkout = wt_repo.as_ref().unwrap_or(repo);
 
-    let base_obj = repo
-        .revparse_single(&base_ref)
-        .map_err(|e| format!("Failed to resolve '{}': {}", base, e))?;
-    let compare_obj = repo
-        .revparse_single(&compare_ref)
-        .map_err(|e| format!("Failed to resolve '{}': {}", compare, e))?;
+    let load = |path: &str, oid: Option<&str>| -> Result<Option<BlobContent>, String> {
+        let Some(oid) = oid else {
+            return Ok(None);
+        };
+        let content = side_content(checkout, path, oid)?;
+        let head = &content[..content.len().min(SNIFF_LEN)];
+        Ok(Some(BlobContent {
+            oid: oid.to_string(),
+            size: content.len() as u64,
+            mime_type: mime::sniff(path, Some(head)),
+            data: base64::engine::general_purpose::STANDARD.encode(&content),
+        }))
+    };
 
-    let base_tree = base_obj
-        .peel_to_tree()
-        .map_err(|e| format!("Failed to get tree for '{}': {}", base, e))?;
-    let compare_tree = compare_obj
-        .peel_to_tree()
-        .map_err(|e| format!("Failed to get tree for '{}': {}", compare, e))?;
+    Ok(BlobPair {
+        old: load(old_path.unwrap_or(path), old_oid)?,
+        new: load(path, new_oid)?,
+    })
+}
 
-    let diff = repo
-        .diff_tree_to_tree(Some(&base_tree), Some(&compare_tree), None)
-        .map_err(|e| format!("Failed to generate diff: {}", e))?;
+/// Lines `start..=end` (1-based, clamped to the file) of one side of a diff,
+/// for expanding the context around hunks. `end: None` reads to the end of
+/// the file. `compare` picks the checkout, as in [`blob_pair`].
+pub fn blob_lines(
+    repo: &Repository,
+    compare: &str,
+    path: &str,
+    oid: &str,
+    start: u32,
+    end: Option<u32>,
+) -> Result<FileLines, String> {
+    let wt_repo = match compare.strip_prefix(WORKTREE_PREFIX) {
+        Some(name) => Some(open_worktree(repo, name)?),
+        None => None,
+    };
+    let checkout = wt_repo.as_ref().unwrap_or(repo);
+    let content = side_content(checkout, path, oid)?;
+    Ok(text_lines(checkout, path, &content, start, end))
+}
+
+/// The whole of `path` as of `spec`, which is a revspec or a
+/// `worktree:<name>` to read the worktree's working directory.
+pub fn file_at_ref(
+    repo: &Repository,
+    spec: &str,
+    side: DiffSide,
+    path: &str,
+) -> Result<FileLines, String> {
+    if let Some(name) = spec.strip_prefix(WORKTREE_PREFIX) {
+        let wt_repo = open_worktree(repo, name)?;
+        let workdir = wt_repo
+            .workdir()
+            .ok_or("Repository has no working directory")?;
+        let content = std::fs::read(checkout_path(workdir, path)?)
+            .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        return Ok(text_lines(&wt_repo, path, &content, 1, None));
+    }
+
+    let commit = resolve_commit(repo, spec, side)?;
+    let blob = commit
+        .tree()
+        .and_then(|tree| tree.get_path(Path::new(path)))
+        .and_then(|entry| entry.to_object(repo))
+        .and_then(|obj| obj.peel_to_blob())
+        .map_err(|e| format!("Failed to read '{}' at '{}': {}", path, spec, e))?;
+    let content = lfs::resolve(repo, blob.content().to_vec());
+    Ok(text_lines(repo, path, &content, 1, None))
+}
 
+/// `path` within `workdir`. As with a git pathspec, it must be relative and
+/// can't climb out of the checkout.
+fn checkout_path(workdir: &Path, path: &str) -> Result<PathBuf, String> {
+    let inside = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(format!("'{}' is outside the working directory", path));
+    }
+    Ok(workdir.join(path))
+}
+
+/// Decode `content` the same way diff hunks are and cut out a line range.
+fn text_lines(
+    repo: &Repository,
+    path: &str,
+    content: &[u8],
+    start: u32,
+    end: Option<u32>,
+) -> FileLines {
+    let declared = working_tree_encoding(repo, path);
+    let decoded = encoding::decode(content, declared.as_deref());
+    let all: Vec<&str> = decoded
+        .text
+        .split_inclusive('\n')
+        .map(|line| line.strip_suffix('\n').unwrap_or(line))
+        .collect();
+
+    let total = all.len();
+    let from = (start.max(1) as usize - 1).min(total);
+    let to = end.map_or(total, |end| (end as usize).min(total)).max(from);
+    FileLines {
+        start: from as u32 + 1,
+        lines: all[from..to].iter().map(|line| line.to_string()).collect(),
+        total: total as u32,
+    }
+}
+
+/// Hunks of a text file along with how its content was decoded.
+#[derive(Default)]
+pub struct FileText {
+    pub hunks: Vec<DiffHunk>,
+    pub encoding: Option<&'static encoding_rs::Encoding>,
+    pub old_encoding: Option<&'static encoding_rs::Encoding>,
+    pub lossy: bool,
+    /// Counted over the whole file, including hunks the limits left out.
+    pub additions: usize,
+    pub deletions: usize,
+    pub truncated: Vec<TruncationReason>,
+}
+
+impl FileText {
+    /// Hunks read from `patch`, or `None` if a line can't be decoded.
+    fn from_patch(
+        patch: &git2::Patch,
+        decode: impl Fn(&[u8]) -> Option<String>,
+        limits: &DiffLimits,
+        lines_before: usize,
+    ) -> Option<FileText> {
+        let mut limit = HunkLimit::new(limits, lines_before);
+        let hunks = patch_hunks(patch, decode, &mut limit)?;
+        let (_, additions, deletions) = patch.line_stats().ok()?;
+        Some(FileText {
+            hunks,
+            additions,
+            deletions,
+            truncated: limit.truncated,
+            ..Default::default()
+        })
+    }
+}
+
+fn working_tree_encoding(repo: &Repository, path: &str) -> Option<String> {
+    repo.get_attr(
+        Path::new(path),
+        "working-tree-encoding",
+        git2::AttrCheckFlags::default(),
+    )
+    .ok()
+    .flatten()
+    .map(str::to_string)
+}
+
+/// Hunks for a text file. Plain UTF-8 comes straight from libgit2's patch;
+/// anything else is decoded and re-diffed, with a lossy per-line decode as
+/// the last resort when the content can't be loaded.
+fn file_text(
+    repo: &Repository,
+    patch: Option<&git2::Patch>,
+    old: Option<(&str, &str)>,
+    new: Option<(&str, &str)>,
+    declared: Option<&str>,
+    settings: &DiffSettings,
+    lines_before: usize,
+) -> FileText {
+    let limits = &settings.limits;
+    let utf8 = patch.and_then(|p| {
+        FileText::from_patch(
+            p,
+            |b| std::str::from_utf8(b).ok().map(str::to_string),
+            limits,
+            lines_before,
+        )
+    });
+    if let Some(text) = utf8 {
+        return FileText {
+            encoding: Some(encoding_rs::UTF_8),
+            ..text
+        };
+    }
+
+    transcoded_text(repo, old, new, declared, settings, lines_before).unwrap_or_else(|| {
+        let lossy = patch.and_then(|p| {
+            FileText::from_patch(
+                p,
+                |b| Some(String::from_utf8_lossy(b).to_string()),
+                limits,
+                lines_before,
+            )
+        });
+        FileText {
+            lossy: true,
+            ..lossy.unwrap_or_default()
+        }
+    })
+}
+
+/// Decode both sides of a non-UTF-8 file and diff the decoded text, so line
+/// content survives intact. `None` if either side's content can't be loaded.
+fn transcoded_text(
+    repo: &Repository,
+    old: Option<(&str, &str)>,
+    new: Option<(&str, &str)>,
+    declared: Option<&str>,
+    settings: &DiffSettings,
+    lines_before: usize,
+) -> Option<FileText> {
+    let load = |side: Option<(&str, &str)>| -> Option<Option<Vec<u8>>> {
+        match side {
+            Some((path, oid)) => Some(Some(side_content(repo, path, oid).ok()?)),
+            None => Some(None),
+        }
+    };
+    let old_content = load(old)?;
+    let new_content = load(new)?;
+    decoded_text(
+        old.map(|(path, _)| path).zip(old_content.as_deref()),
+        new.map(|(path, _)| path).zip(new_content.as_deref()),
+        declared,
+        settings,
+        lines_before,
+    )
+}
+
+/// Decode both sides' content and diff the decoded text. `None` if libgit2
+/// can't diff the buffers. `lines_before` counts the changed lines in the
+/// diff's earlier files, for `max_total_lines`.
+pub fn decoded_text(
+    old: Option<(&str, &[u8])>,
+    new: Option<(&str, &[u8])>,
+    declared: Option<&str>,
+    settings: &DiffSettings,
+    lines_before: usize,
+) -> Option<FileText> {
+    let old_text = old.map(|(_, content)| encoding::decode(content, declared));
+    let new_text = new.map(|(_, content)| encoding::decode(content, declared));
+
+    let old_buf = old_text.as_ref().map_or("", |d| d.text.as_str());
+    let new_buf = new_text.as_ref().map_or("", |d| d.text.as_str());
+    let patch = git2::Patch::from_buffers(
+        old_buf.as_bytes(),
+        old.map(|(path, _)| Path::new(path)),
+        new_buf.as_bytes(),
+        new.map(|(path, _)| Path::new(path)),
+        Some(&mut diff_options(settings)),
+    )
+    .ok()?;
+
+    let old_encoding = old_text.as_ref().map(|d| d.encoding);
+    let new_encoding = new_text.as_ref().map(|d| d.encoding);
+    let text = FileText::from_patch(
+        &patch,
+        |b| std::str::from_utf8(b).ok().map(str::to_string),
+        &settings.limits,
+        lines_before,
+    )?;
+    Some(FileText {
+        encoding: new_encoding.or(old_encoding),
+        old_encoding,
+        lossy: [&old_text, &new_text]
+            .iter()
+            .any(|d| d.as_ref().is_some_and(|d| d.lossy)),
+        ..text
+    })
+}
+
+/// Collect a patch's hunks, decoding each line with `decode`. Bails out with
+/// `None` as soon as a line can't be decoded. Stops at the first hunk `limit`
+/// turns away, without reading its lines.
+pub fn patch_hunks(
+    patch: &git2::Patch,
+    decode: impl Fn(&[u8]) -> Option<String>,
+    limit: &mut HunkLimit,
+) -> Option<Vec<DiffHunk>> {
+    let mut hunks = Vec::new();
+
+    for hunk_idx in 0..patch.num_hunks() {
+        let (hunk, _count) = patch.hunk(hunk_idx).ok()?;
+        let line_count = patch.num_lines_in_hunk(hunk_idx).unwrap_or(0);
+        let changed = (0..line_count)
+            .filter_map(|line_idx| patch.line_in_hunk(hunk_idx, line_idx).ok())
+            .filter(|line| matches!(line.origin(), '+' | '-'))
+            .count();
+        if !limit.admit(line_count, changed) {
+            break;
+        }
+        let mut lines = Vec::new();
+
+        for line_idx in 0..line_count {
+            if let Ok(line) = patch.line_in_hunk(hunk_idx, line_idx) {
+                let line_type = match line.origin() {
+                    '+' => LineType::Add,
+                    '-' => LineType::Delete,
+                    ' ' => LineType::Context,
+                    // "\ No newline at end of file" markers ('=', '>', '<').
+                    // The affected line already reports it via `no_newline`.
+                    _ => continue,
+                };
+
+                let raw = line.content();
+                let no_newline = !raw.ends_with(b"\n");
+                let mut content = decode(raw)?.trim_end_matches('\n').to_string();
+                limit.cut(&mut content);
+
+                lines.push(DiffLine {
+                    line_type,
+                    content,
+                    old_num: line.old_lineno(),
+                    new_num: line.new_lineno(),
+                    no_newline,
+                    changes: Vec::new(),
+                });
+            }
+        }
+
+        hunks.push(DiffHunk {
+            old_start: hunk.old_start(),
+            old_lines: hunk.old_lines(),
+            new_start: hunk.new_start(),
+            new_lines: hunk.new_lines(),
+            lines,
+        });
+    }
+
+    Some(hunks)
+}
+
+/// Whether the file gained or lost its final newline. Only lines near the end
+/// of the file can lack one, so this only fires when a hunk reaches EOF.
+pub fn eof_newline_change(hunks: &[DiffHunk]) -> Option<EofNewline> {
+    let lines = || hunks.iter().flat_map(|h| &h.lines);
+    let old_missing = lines().any(|l| l.no_newline && !matches!(l.line_type, LineType::Add));
+    let new_missing = lines().any(|l| l.no_newline && !matches!(l.line_type, LineType::Delete));
+    match (old_missing, new_missing) {
+        (true, false) => Some(EofNewline::Added),
+        (false, true) => Some(EofNewline::Removed),
+        _ => None,
+    }
+}
+
+/// Detect a diff that only converts line endings: every deleted line has a
+/// matching added line that differs by nothing but a trailing `\r`.
+pub fn line_ending_change(hunks: &[DiffHunk]) -> Option<LineEndingChange> {
+    let lines = || hunks.iter().flat_map(|h| &h.lines);
+    let deleted: Vec<&str> = lines()
+        .filter(|l| matches!(l.line_type, LineType::Delete))
+        .map(|l| l.content.as_str())
+        .collect();
+    let added: Vec<&str> = lines()
+        .filter(|l| matches!(l.line_type, LineType::Add))
+        .map(|l| l.content.as_str())
+        .collect();
+
+    if deleted.is_empty() || deleted.len() != added.len() {
+        return None;
+    }
+    let pairs = || deleted.iter().zip(&added);
+    let same_text =
+        pairs().all(|(old, new)| old.trim_end_matches('\r') == new.trim_end_matches('\r'));
+    // Identical pairs come from a final-newline change, not a conversion.
+    if !same_text || pairs().all(|(old, new)| old == new) {
+        return None;
+    }
+
+    let ending = |side: &[&str]| {
+        let crlf = side.iter().filter(|l| l.ends_with('\r')).count();
+        match crlf {
+            0 => LineEnding::Lf,
+            n if n == side.len() => LineEnding::Crlf,
+            _ => LineEnding::Mixed,
+        }
+    };
+    Some(LineEndingChange {
+        from: ending(&deleted),
+        to: ending(&added),
+    })
+}
+
+/// A text file's hunks, with intraline ranges, and what they say about the
+/// file as a whole.
+pub struct TextChanges {
+    pub eof_newline: Option<EofNewline>,
+    pub line_endings: Option<LineEndingChange>,
+    pub additions: usize,
+    pub deletions: usize,
+    /// Empty when every change is a line-ending conversion.
+    pub hunks: Vec<DiffHunk>,
+    pub truncated: Vec<TruncationReason>,
+}
+
+/// `additions` and `deletions` cover the whole file; `truncated` says what
+/// the limits left out of `hunks`.
+pub fn text_changes(
+    mut hunks: Vec<DiffHunk>,
+    additions: usize,
+    deletions: usize,
+    truncated: Vec<TruncationReason>,
+) -> TextChanges {
+    let eof_newline = eof_newline_change(&hunks);
+    // Cut hunks or lines can't show that nothing but line endings changed.
+    let line_endings = truncated
+        .is_empty()
+        .then(|| line_ending_change(&hunks))
+        .flatten();
+    if line_endings.is_some() {
+        hunks.clear();
+    }
+    for hunk in &mut hunks {
+        intraline::annotate(&mut hunk.lines);
+    }
+    TextChanges {
+        eof_newline,
+        line_endings,
+        additions,
+        deletions,
+        hunks,
+        truncated,
+    }
+}
+
+/// Applies the [`DiffLimits`] to one file's hunks as they're read. Hunks are
+/// kept or dropped whole so their headers stay true.
+pub struct HunkLimit<'a> {
+    limits: &'a DiffLimits,
+    /// Changed lines in the diff's earlier files.
+    lines_before: usize,
+    lines: usize,
+    changed: usize,
+    stopped: bool,
+    pub truncated: Vec<TruncationReason>,
+}
+
+impl<'a> HunkLimit<'a> {
+    pub fn new(limits: &'a DiffLimits, lines_before: usize) -> Self {
+        Self {
+            limits,
+            lines_before,
+            lines: 0,
+            changed: 0,
+            stopped: false,
+            truncated: Vec::new(),
+        }
+    }
+
+    /// Whether the next hunk, with `lines` lines of which `changed` are
+    /// added or deleted, still fits. Once one doesn't, none after it do.
+    pub fn admit(&mut self, lines: usize, changed: usize) -> bool {
+        let over = |limit: usize, count: usize| limit > 0 && count > limit;
+        if self.stopped {
+            return false;
+        }
+        let reason = if over(self.limits.max_file_lines, self.lines + lines) {
+            TruncationReason::FileLines
+        } else if over(
+            self.limits.max_total_lines,
+            self.lines_before + self.changed + changed,
+        ) {
+            TruncationReason::TotalLines
+        } else {
+            self.lines += lines;
+            self.changed += changed;
+            return true;
+        };
+        self.stopped = true;
+        self.truncated.push(reason);
+        false
+    }
+
+    /// Cut `content` down to `max_line_length` characters.
+    pub fn cut(&mut self, content: &mut String) {
+        if self.limits.max_line_length == 0 {
+            return;
+        }
+        if let Some((end, _)) = content.char_indices().nth(self.limits.max_line_length) {
+            content.truncate(end);
+            if !self.truncated.contains(&TruncationReason::LineLength) {
+                self.truncated.push(TruncationReason::LineLength);
+            }
+        }
+    }
+}
+
+/// Apply `limit` to hunks that were built up front, as when parsing a patch
+/// file.
+pub fn limit_hunks(hunks: &mut Vec<DiffHunk>, limit: &mut HunkLimit) {
+    let mut kept = 0;
+    for hunk in hunks.iter() {
+        let changed = hunk
+            .lines
+            .iter()
+            .filter(|l| !matches!(l.line_type, LineType::Context))
+            .count();
+        if !limit.admit(hunk.lines.len(), changed) {
+            break;
+        }
+        kept += 1;
+    }
+    hunks.truncate(kept);
+    for line in hunks.iter_mut().flat_map(|h| &mut h.lines) {
+        limit.cut(&mut line.content);
+    }
+}
+
+/// Whether either side is over `limits.max_file_size`.
+pub fn over_size_limit(limits: &DiffLimits, sizes: [Option<u64>; 2]) -> bool {
+    limits.max_file_size > 0
+        && sizes
+            .iter()
+            .flatten()
+            .any(|&size| size > limits.max_file_size)
+}
+
+fn file_status(delta: &git2::DiffDelta) -> FileStatus {
+    match delta.status() {
+        git2::Delta::Added | git2::Delta::Untracked => FileStatus::Added,
+        git2::Delta::Deleted => FileStatus::Deleted,
+        git2::Delta::Modified => FileStatus::Modified,
+        git2::Delta::Renamed => FileStatus::Renamed,
+        git2::Delta::Copied => FileStatus::Copied,
+        git2::Delta::Typechange => FileStatus::Typechange,
+        git2::Delta::Unreadable => FileStatus::Unreadable,
+        git2::Delta::Conflicted => FileStatus::Conflicted,
+        // Only emitted when asked for (INCLUDE_UNMODIFIED / INCLUDE_IGNORED),
+        // which we never do.
+        git2::Delta::Unmodified | git2::Delta::Ignored => FileStatus::Modified,
+    }
+}
+
+/// The path a delta is reported under: the new path, or the old one for
+/// deletions.
+pub fn delta_path(delta: &git2::DiffDelta) -> String {
+    delta
+        .new_file()
+        .path()
+        .or_else(|| delta.old_file().path())
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// The source path of a rename or copy.
+fn copied_from(delta: &git2::DiffDelta, status: &FileStatus) -> Option<String> {
+    if !matches!(status, FileStatus::Renamed | FileStatus::Copied) {
+        return None;
+    }
+    delta
+        .old_file()
+        .path()
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+fn file_summaries(
+    repo: &Repository,
+    diff: &git2::Diff,
+    settings: &DiffSettings,
+) -> Result<Vec<FileSummary>, String> {
+    let similarity = similarity_by_path(diff)?;
     let mut files = Vec::new();
+    for (idx, delta) in diff.deltas().enumerate() {
+        let status = file_status(&delta);
+        let path = delta_path(&delta);
+        let old_path = copied_from(&delta, &status);
+        let similarity = old_path.as_ref().and(similarity.get(&path).copied());
+
+        let old_mode = file_mode(delta.old_file().mode());
+        let new_mode = file_mode(delta.new_file().mode());
+        let (old_oid, old_size) = side_info(repo, &delta.old_file());
+        let (new_oid, new_size) = side_info(repo, &delta.new_file());
+        // Binary patches have no lines, so they count as zero either way.
+        // Neither do submodules and LFS pointers, whose patches only change
+        // the pointer; LFS content is counted once the file is loaded. Files
+        // over the size limit aren't read at all.
+        let skip = old_mode == Some(FileMode::Commit)
+            || new_mode == Some(FileMode::Commit)
+            || over_size_limit(&settings.limits, [old_size, new_size])
+            || lfs::pointer_change(repo, old_oid.as_deref(), new_oid.as_deref()).is_some();
+        let (additions, deletions) = (!skip)
+            .then(|| git2::Patch::from_diff(diff, idx).ok().flatten())
+            .flatten()
+            .and_then(|p| p.line_stats().ok())
+            .map_or((0, 0), |(_, additions, deletions)| (additions, deletions));
+
+        files.push(FileSummary {
+            status,
+            old_path,
+            similarity,
+            old_mode,
+            new_mode,
+            old_oid,
+            new_oid,
+            additions,
+            deletions,
+            path,
+        });
+    }
+    Ok(files)
+}
 
+/// `lines_before` counts the changed lines in files that come before this
+/// diff's in the full diff, for `max_total_lines`.
+fn diff_files(
+    repo: &Repository,
+    diff: &git2::Diff,
+    settings: &DiffSettings,
+    mut lines_before: usize,
+) -> Result<Vec<DiffFile>, String> {
+    let similarity = similarity_by_path(diff)?;
+    let mut files = Vec::new();
     for idx in 0..diff.deltas().len() {
         let delta = diff.get_delta(idx).unwrap();
 
-        let status = match delta.status() {
-            git2::Delta::Added => FileStatus::Added,
-            git2::Delta::Deleted => FileStatus::Deleted,
-            git2::Delta::Modified => FileStatus::Modified,
-            git2::Delta::Renamed => FileStatus::Renamed,
-            _ => FileStatus::Modified,
+        let status = file_status(&delta);
+        let path = delta_path(&delta);
+        let old_path = copied_from(&delta, &status);
+        let similarity = old_path.as_ref().and(similarity.get(&path).copied());
+
+        let (old_oid, old_size) = side_info(repo, &delta.old_file());
+        let (new_oid, new_size) = side_info(repo, &delta.new_file());
+        let old_mode = file_mode(delta.old_file().mode());
+        let new_mode = file_mode(delta.new_file().mode());
+
+        // A gitlink's patch is just `Subproject commit <oid>`; report the
+        // commits themselves instead.
+        let gitlink = |mode: Option<FileMode>, oid: &Option<String>| {
+            oid.clone().filter(|_| mode == Some(FileMode::Commit))
+        };
+        let submodule = (old_mode == Some(FileMode::Commit) || new_mode == Some(FileMode::Commit))
+            .then(|| {
+                submodule_change(
+                    repo,
+                    &path,
+                    gitlink(old_mode, &old_oid),
+                    gitlink(new_mode, &new_oid),
+                    settings,
+                )
+            });
+
+        // LFS pointers are diffed by the objects they point to when those are
+        // in the local store. Otherwise only the pointers are reported.
+        let lfs = submodule
+            .is_none()
+            .then(|| lfs::pointer_change(repo, old_oid.as_deref(), new_oid.as_deref()))
+            .flatten();
+        let lfs_resolved = lfs.as_ref().map(|lfs| {
+            [&lfs.old, &lfs.new]
+                .iter()
+                .all(|pointer| pointer.as_ref().is_none_or(|p| p.present))
+        });
+
+        let old_pointer = lfs.as_ref().and_then(|l| l.old.as_ref());
+        let new_pointer = lfs.as_ref().and_then(|l| l.new.as_ref());
+
+        // The size of what's reviewed, not of the pointer.
+        let old_size = old_pointer.map_or(old_size, |p| Some(p.size));
+        let new_size = new_pointer.map_or(new_size, |p| Some(p.size));
+        // LFS objects past `lfs::MAX_OBJECT_SIZE` are never read, limits or
+        // not.
+        let too_large = submodule.is_none()
+            && (over_size_limit(&settings.limits, [old_size, new_size])
+                || [old_pointer, new_pointer]
+                    .iter()
+                    .flatten()
+                    .any(|p| p.size > lfs::MAX_OBJECT_SIZE));
+
+        let old_side = old_oid
+            .as_deref()
+            .map(|oid| (old_path.as_deref().unwrap_or(&path), oid));
+        let new_side = new_oid.as_deref().map(|oid| (path.as_str(), oid));
+
+        let patch = (submodule.is_none() && lfs.is_none() && !too_large)
+            .then(|| git2::Patch::from_diff(diff, idx).ok().flatten())
+            .flatten();
+        // Only the start of a side is needed to sniff it, so LFS objects are
+        // read no further than that.
+        let side_head =
+            |side: Option<(&str, &str)>, pointer: Option<&LfsPointer>, len: usize| match pointer {
+                Some(pointer) => lfs::object_head(repo, pointer, len),
+                None => side
+                    .and_then(|(path, oid)| side_content(repo, path, oid).ok())
+                    .map(|mut content| {
+                        content.truncate(len);
+                        content
+                    }),
+            };
+        let binary = match lfs_resolved {
+            Some(true) if !too_large => [(old_side, old_pointer), (new_side, new_pointer)]
+                .into_iter()
+                .filter(|(side, _)| side.is_some())
+                .any(|(side, pointer)| {
+                    side_head(side, pointer, encoding::BINARY_CHECK_LEN)
+                        .is_some_and(|head| encoding::is_binary(&head))
+                }),
+            Some(_) => false,
+            // The binary flag is only reliable once the patch has loaded
+            // content.
+            None => {
+                submodule.is_none()
+                    && patch
+                        .as_ref()
+                        .map_or(delta.flags().is_binary(), |p| p.delta().flags().is_binary())
+            }
+        };
+
+        let head = if binary && !too_large {
+            match new_side {
+                Some(_) => side_head(new_side, new_pointer, SNIFF_LEN),
+                None => side_head(old_side, old_pointer, SNIFF_LEN),
+            }
+        } else {
+            None
+        };
+        let mime_type = mime::sniff(&path, head.as_deref());
+
+        let declared = working_tree_encoding(repo, &path);
+        let utf16 = binary
+            && head
+                .as_deref()
+                .is_some_and(|h| encoding::is_utf16(h, declared.as_deref()));
+        let binary = binary && !utf16;
+
+        let skip_text = binary || too_large || submodule.is_some() || lfs_resolved == Some(false);
+        let text = if skip_text {
+            FileText::default()
+        } else {
+            file_text(
+                repo,
+                patch.as_ref().filter(|_| !utf16),
+                old_side,
+                new_side,
+                declared.as_deref(),
+                settings,
+                lines_before,
+            )
         };
 
-        let path = delta
-            .new_file()
-            .path()
-            .or_else(|| delta.old_file().path())
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_default();
-
-        let old_path = if matches!(status, FileStatus::Renamed) {
-            delta
-                .old_file()
-                .path()
-                .map(|p| p.to_string_lossy().to_string())
-        } else {
-            None
+        let mut changes = text_changes(text.hunks, text.additions, text.deletions, text.truncated);
+        if too_large {
+            changes.truncated.push(TruncationReason::FileSize);
+        }
+        lines_before += changes.additions + changes.deletions;
+
+        files.push(DiffFile {
+            path,
+            status,
+            old_path,
+            similarity,
+            old_mode,
+            new_mode,
+            old_oid,
+            new_oid,
+            old_size,
+            new_size,
+            binary,
+            mime_type,
+            encoding: text.encoding.map(|e| e.name().to_string()),
+            old_encoding: text
+                .old_encoding
+                .filter(|old| Some(*old) != text.encoding)
+                .map(|e| e.name().to_string()),
+            lossy: text.lossy,
+            eof_newline: changes.eof_newline,
+            line_endings: changes.line_endings,
+            additions: changes.additions,
+            deletions: changes.deletions,
+            hunks: changes.hunks,
+            submodule,
+            lfs,
+            truncated: changes.truncated,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Old and new commits of a changed submodule, with their summaries when the
+/// submodule is cloned locally.
+fn submodule_change(
+    repo: &Repository,
+    path: &str,
+    old_oid: Option<String>,
+    new_oid: Option<String>,
+    settings: &DiffSettings,
+) -> SubmoduleChange {
+    let submodule = open_submodule(repo, path);
+    let find = |oid: &str| {
+        let oid = git2::Oid::from_str(oid).ok()?;
+        submodule.as_ref()?.find_commit(oid).ok()
+    };
+    let commit = |oid: &Option<String>| {
+        oid.as_ref().map(|oid| SubmoduleCommit {
+            oid: oid.clone(),
+            summary: find(oid).and_then(|c| c.summary().map(str::to_string)),
+        })
+    };
+
+    let files = submodule
+        .as_ref()
+        .filter(|_| settings.recurse_submodules)
+        .and_then(|sub| {
+            let tree = |oid: &Option<String>| match oid {
+                Some(oid) => find(oid).and_then(|c| c.tree().ok()).map(Some),
+                None => Some(None),
+            };
+            let (old_tree, new_tree) = (tree(&old_oid)?, tree(&new_oid)?);
+            let mut diff = sub
+                .diff_tree_to_tree(
+                    old_tree.as_ref(),
+                    new_tree.as_ref(),
+                    Some(&mut diff_options(settings)),
+                )
+                .ok()?;
+            find_renames(&mut diff, &settings.renames).ok()?;
+            // The submodule's files count against limits of their own.
+            diff_files(sub, &diff, settings, 0).ok()
+        });
+
+    SubmoduleChange {
+        old_commit: commit(&old_oid),
+        new_commit: commit(&new_oid),
+        files,
+    }
+}
+
+/// A submodule's own repository, if it has been cloned.
+fn open_submodule(repo: &Repository, path: &str) -> Option<Repository> {
+    repo.find_submodule(path)
+        .ok()
+        .and_then(|s| s.open().ok())
+        .or_else(|| Repository::open(repo.workdir()?.join(path)).ok())
+}
+
+/// Commit builders shared by the modules' tests. They write trees straight to
+/// the object database, leaving the index and working directory alone.
+#[cfg(test)]
+pub mod test_support {
+    use git2::{Oid, Repository};
+
+    /// Commit `parent`'s tree, or an empty one, with `files` written, or
+    /// removed where their content is `None`. No ref is moved.
+    pub fn commit(
+        repo: &Repository,
+        parent: Option<Oid>,
+        files: &[(&str, Option<&str>)],
+        msg: &str,
+    ) -> Oid {
+        commit_to(repo, None, parent, files, msg)
+    }
+
+    /// Like [`commit`], on top of `HEAD`, which is moved to the new commit.
+    pub fn commit_to_head(repo: &Repository, files: &[(&str, Option<&str>)], msg: &str) -> Oid {
+        let parent = repo.head().ok().and_then(|h| h.target());
+        commit_to(repo, Some("HEAD"), parent, files, msg)
+    }
+
+    fn commit_to(
+        repo: &Repository,
+        update_ref: Option<&str>,
+        parent: Option<Oid>,
+        files: &[(&str, Option<&str>)],
+        msg: &str,
+    ) -> Oid {
+        let parent = parent.map(|oid| repo.find_commit(oid).unwrap());
+        let base = match &parent {
+            Some(parent) => parent.tree().unwrap(),
+            None => {
+                let empty = repo.treebuilder(None).unwrap().write().unwrap();
+                repo.find_tree(empty).unwrap()
+            }
         };
-
-        let mut hunks = Vec::new();
-
-        if let Ok(patch) = git2::Patch::from_diff(&diff, idx) {
-            if let Some(patch) = patch {
-                for hunk_idx in 0..patch.num_hunks() {
-                    let (hunk, _count) = patch.hunk(hunk_idx).unwrap();
-                    let mut lines = Vec::new();
-
-                    for line_idx in 0..patch.num_lines_in_hunk(hunk_idx).unwrap_or(0) {
-                        if let Ok(line) = patch.line_in_hunk(hunk_idx, line_idx) {
-                            let line_type = match line.origin() {
-                                '+' => LineType::Add,
-                                '-' => LineType::Delete,
-                                _ => LineType::Context,
-                            };
-
-                            let content = std::str::from_utf8(line.content())
-                                .unwrap_or("")
-                                .trim_end_matches('\n')
-                                .to_string();
-
-                            lines.push(DiffLine {
-                                line_type,
-                                content,
-                                old_num: line.old_lineno(),
-                                new_num: line.new_lineno(),
-                            });
-                        }
-                    }
-
-                    hunks.push(DiffHunk {
-                        old_start: hunk.old_start(),
-                        old_lines: hunk.old_lines(),
-                        new_start: hunk.new_start(),
-                        new_lines: hunk.new_lines(),
-                        lines,
-                    });
+        let mut update = git2::build::TreeUpdateBuilder::new();
+        for (path, content) in files {
+            match content {
+                Some(content) => {
+                    let blob = repo.blob(content.as_bytes()).unwrap();
+                    update.upsert(*path, blob, git2::FileMode::Blob);
+                }
+                None => {
+                    update.remove(*path);
                 }
             }
         }
-
-        files.push(DiffFile {
-            path,
-            status,
-            old_path,
-            hunks,
-        });
+        let tree = update.create_updated(repo, &base).unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(update_ref, &sig, &sig, msg, &tree, &parents)
+            .unwrap()
     }
-
-    Ok(DiffResult {
-        base_ref: base.to_string(),
-        compare_ref: compare.to_string(),
-        files,
-    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::CommitRange;
     use std::fs;
     use std::path::Path;
 
@@ -263,13 +1848,15 @@ mod tests {
         fs::write(dir.path().join("test.txt"), "hello\nworld\n").unwrap();
         make_commit(&repo, dir.path(), "modify");
 
-        let diff = generate_diff(&repo, "main", "feature").unwrap();
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
         assert_eq!(diff.base_ref, "main");
         assert_eq!(diff.compare_ref, "feature");
         assert_eq!(diff.files.len(), 1);
         assert_eq!(diff.files[0].path, "test.txt");
         assert!(matches!(diff.files[0].status, FileStatus::Modified));
         assert!(!diff.files[0].hunks.is_empty());
+        assert_eq!((diff.files[0].additions, diff.files[0].deletions), (2, 1));
+        assert_eq!((diff.stats.files, diff.stats.additions), (1, 2));
     }
 
     #[test]
@@ -282,7 +1869,7 @@ mod tests {
         fs::write(dir.path().join("new.txt"), "new file\n").unwrap();
         make_commit(&repo, dir.path(), "add file");
 
-        let diff = generate_diff(&repo, "main", "feature").unwrap();
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
         let new_file = diff.files.iter().find(|f| f.path == "new.txt").unwrap();
         assert!(matches!(new_file.status, FileStatus::Added));
     }
@@ -306,8 +1893,855 @@ mod tests {
                 .unwrap();
         }
 
-        let diff = generate_diff(&repo, "main", "feature").unwrap();
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
         let deleted = diff.files.iter().find(|f| f.path == "test.txt").unwrap();
         assert!(matches!(deleted.status, FileStatus::Deleted));
     }
+
+    #[test]
+    fn test_generate_diff_resolves_revspecs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let initial = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("v1.0", initial.as_object(), false)
+            .unwrap();
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("test.txt"), "hello\nworld\n").unwrap();
+        make_commit(&repo, dir.path(), "modify");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+
+        let by_tag = generate_diff(&repo, "v1.0", "feature", &DiffParams::default()).unwrap();
+        assert_eq!(by_tag.base_oid, initial.id().to_string());
+        assert_eq!(by_tag.compare_oid, head.id().to_string());
+        assert_eq!(by_tag.files.len(), 1);
+
+        let short = &head.id().to_string()[..7];
+        let by_sha = generate_diff(&repo, "HEAD~1", short, &DiffParams::default()).unwrap();
+        assert_eq!(by_sha.base_oid, initial.id().to_string());
+        assert_eq!(by_sha.compare_oid, head.id().to_string());
+        assert_eq!(by_sha.compare_ref, short);
+    }
+
+    #[test]
+    fn test_generate_diff_reports_failing_side() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+
+        let err = resolve_commit(&repo, "no-such-ref", DiffSide::Compare).unwrap_err();
+        assert_eq!(err.role, SpecRole::Compare);
+        assert_eq!(err.kind, ResolveErrorKind::NotFound);
+
+        let msg = generate_diff(&repo, "no-such-ref", "HEAD", &DiffParams::default()).unwrap_err();
+        assert!(msg.contains("base 'no-such-ref'"));
+    }
+
+    fn add_worktree(repo: &Repository, name: &str, path: &Path) {
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let branch = repo.branch(name, &head, false).unwrap();
+        let mut opts = git2::WorktreeAddOptions::new();
+        opts.reference(Some(branch.get()));
+        repo.worktree(name, path, Some(&opts)).unwrap();
+    }
+
+    #[test]
+    fn test_list_worktrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let wt_dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        add_worktree(&repo, "agent", &wt_dir.path().join("agent"));
+
+        let refs = list_refs(&repo).unwrap();
+        let wt = refs
+            .iter()
+            .find(|r| matches!(r.ref_type, RefType::Worktree))
+            .unwrap();
+        assert_eq!(wt.name, "worktree:agent");
+
+        let info = wt.worktree.as_ref().unwrap();
+        assert_eq!(info.branch.as_deref(), Some("agent"));
+        assert!(!info.locked);
+        assert!(!info.prunable);
+    }
+
+    #[test]
+    fn test_generate_diff_worktree_includes_uncommitted() {
+        let dir = tempfile::tempdir().unwrap();
+        let wt_dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let wt_path = wt_dir.path().join("agent");
+        add_worktree(&repo, "agent", &wt_path);
+
+        fs::write(wt_path.join("test.txt"), "hello\nagain\n").unwrap();
+        fs::write(wt_path.join("untracked.txt"), "scratch\n").unwrap();
+
+        let params = DiffParams {
+            mode: CompareMode::Workdir,
+            ..Default::default()
+        };
+        let diff = generate_diff(&repo, "main", "worktree:agent", &params).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(diff.compare_oid, head.id().to_string());
+
+        let modified = diff.files.iter().find(|f| f.path == "test.txt").unwrap();
+        assert!(matches!(modified.status, FileStatus::Modified));
+        let untracked = diff
+            .files
+            .iter()
+            .find(|f| f.path == "untracked.txt")
+            .unwrap();
+        assert!(matches!(untracked.status, FileStatus::Added));
+        assert!(!untracked.hunks.is_empty());
+    }
+
+    #[test]
+    fn test_generate_diff_index_and_workdir_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+
+        // Stage one change, then leave a second one unstaged on top of it.
+        fs::write(dir.path().join("staged.txt"), "staged\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+        fs::write(dir.path().join("test.txt"), "hello\nunstaged\n").unwrap();
+
+        let paths = |mode| -> Vec<String> {
+            let diff = generate_diff(
+                &repo,
+                "main",
+                "",
+                &DiffParams {
+                    mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            diff.files.into_iter().map(|f| f.path).collect()
+        };
+
+        assert_eq!(paths(CompareMode::Index), vec!["staged.txt"]);
+        assert_eq!(paths(CompareMode::WorkdirIndex), vec!["test.txt"]);
+        let mut workdir = paths(CompareMode::Workdir);
+        workdir.sort();
+        assert_eq!(workdir, vec!["staged.txt", "test.txt"]);
+
+        // The checked-out branch may be named; any other ref is refused
+        // rather than silently swapped for the checkout.
+        let params = DiffParams {
+            mode: CompareMode::Workdir,
+            ..Default::default()
+        };
+        assert!(generate_diff(&repo, "main", "main", &params).is_ok());
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("other", &head, false).unwrap();
+        fs::write(dir.path().join("other.txt"), "x\n").unwrap();
+        make_commit(&repo, dir.path(), "move main on");
+        let err = generate_diff(&repo, "main", "other", &params).unwrap_err();
+        assert!(err.contains("isn't checked out"), "{}", err);
+    }
+
+    #[test]
+    fn test_generate_diff_merge_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let fork_point = repo.head().unwrap().peel_to_commit().unwrap();
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("feature.txt"), "feature\n").unwrap();
+        make_commit(&repo, dir.path(), "feature work");
+
+        // Move main on after feature branched.
+        repo.set_head("refs/heads/main").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        fs::write(dir.path().join("main.txt"), "main\n").unwrap();
+        make_commit(&repo, dir.path(), "main work");
+
+        let direct = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
+        let mut paths: Vec<_> = direct.files.iter().map(|f| f.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["feature.txt", "main.txt"]);
+
+        let params = DiffParams {
+            merge_base: true,
+            ..Default::default()
+        };
+        let three_dot = generate_diff(&repo, "main", "feature", &params).unwrap();
+        assert_eq!(three_dot.files.len(), 1);
+        assert_eq!(three_dot.files[0].path, "feature.txt");
+        assert_eq!(three_dot.merge_base, Some(fork_point.id().to_string()));
+        assert_eq!(three_dot.ahead, 1);
+        assert_eq!(three_dot.behind, 1);
+    }
+
+    #[test]
+    fn test_generate_diff_detects_renames_and_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let body: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        fs::write(dir.path().join("lib.rs"), &body).unwrap();
+        fs::write(dir.path().join("old.rs"), body.replace("line", "row")).unwrap();
+        make_commit(&repo, dir.path(), "add sources");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        let mut renamed = fs::read_to_string(dir.path().join("old.rs")).unwrap();
+        renamed.push_str("row 21\n");
+        fs::remove_file(dir.path().join("old.rs")).unwrap();
+        fs::write(dir.path().join("new.rs"), renamed).unwrap();
+        fs::write(dir.path().join("lib.rs"), format!("{}line 21\n", body)).unwrap();
+        fs::write(dir.path().join("copy.rs"), &body).unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("old.rs")).unwrap();
+        index.write().unwrap();
+        make_commit(&repo, dir.path(), "move and copy");
+
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
+        let rename = diff.files.iter().find(|f| f.path == "new.rs").unwrap();
+        assert!(matches!(rename.status, FileStatus::Renamed));
+        assert_eq!(rename.old_path.as_deref(), Some("old.rs"));
+        assert!(rename.similarity.unwrap() >= 90);
+        let copy = diff.files.iter().find(|f| f.path == "copy.rs").unwrap();
+        assert!(matches!(copy.status, FileStatus::Copied));
+        assert_eq!(copy.old_path.as_deref(), Some("lib.rs"));
+        assert_eq!(copy.similarity, Some(100));
+
+        let params = DiffParams {
+            settings: DiffSettings {
+                renames: RenameSettings {
+                    renames: false,
+                    copies: false,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let plain = generate_diff(&repo, "main", "feature", &params).unwrap();
+        assert!(plain
+            .files
+            .iter()
+            .any(|f| f.path == "old.rs" && matches!(f.status, FileStatus::Deleted)));
+    }
+
+    fn commit_entries(repo: &Repository, branch: &str, entries: &[(&str, &[u8], i32)]) {
+        let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, content, mode) in entries {
+            let oid = if *mode == 0o160000 && content.is_empty() {
+                git2::Oid::from_bytes(&[0xab; 20]).unwrap()
+            } else if *mode == 0o160000 {
+                git2::Oid::from_str(std::str::from_utf8(content).unwrap()).unwrap()
+            } else {
+                repo.blob(content).unwrap()
+            };
+            builder.insert(name, oid, *mode).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.commit(
+            Some(&format!("refs/heads/{}", branch)),
+            &sig,
+            &sig,
+            branch,
+            &tree,
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_generate_diff_reports_modes_and_typechanges() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        commit_entries(
+            &repo,
+            "before",
+            &[
+                ("run.sh", b"echo hi\n", 0o100644),
+                ("link", b"target\n", 0o100644),
+            ],
+        );
+        commit_entries(
+            &repo,
+            "after",
+            &[
+                ("run.sh", b"echo hi\n", 0o100755),
+                ("link", b"target", 0o120000),
+                ("vendor", b"", 0o160000),
+            ],
+        );
+
+        let diff = generate_diff(&repo, "before", "after", &DiffParams::default()).unwrap();
+        let file = |path: &str| diff.files.iter().find(|f| f.path == path).unwrap();
+
+        let script = file("run.sh");
+        assert!(matches!(script.status, FileStatus::Modified));
+        assert_eq!(script.old_mode, Some(FileMode::Blob));
+        assert_eq!(script.new_mode, Some(FileMode::BlobExecutable));
+
+        let link = file("link");
+        assert!(matches!(link.status, FileStatus::Typechange));
+        assert_eq!(link.new_mode, Some(FileMode::Link));
+
+        let submodule = file("vendor");
+        assert!(matches!(submodule.status, FileStatus::Added));
+        assert_eq!(submodule.old_mode, None);
+        assert_eq!(submodule.new_mode, Some(FileMode::Commit));
+    }
+
+    #[test]
+    fn test_generate_diff_submodules() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        // A submodule cloned at `lib` inside the parent's working directory.
+        let sub = Repository::init(dir.path().join("lib")).unwrap();
+        let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+        let sub_commit = |message: &str, content: &[u8], parents: &[&git2::Commit]| {
+            let mut builder = sub.treebuilder(None).unwrap();
+            builder
+                .insert("lib.rs", sub.blob(content).unwrap(), 0o100644)
+                .unwrap();
+            let tree = sub.find_tree(builder.write().unwrap()).unwrap();
+            sub.commit(None, &sig, &sig, message, &tree, parents)
+                .unwrap()
+        };
+        let v1 = sub_commit("First", b"one\n", &[]);
+        let v2 = sub_commit("Second", b"one\ntwo\n", &[&sub.find_commit(v1).unwrap()]);
+        commit_entries(
+            &repo,
+            "before",
+            &[("lib", v1.to_string().as_bytes(), 0o160000)],
+        );
+        commit_entries(
+            &repo,
+            "after",
+            &[("lib", v2.to_string().as_bytes(), 0o160000)],
+        );
+
+        let mut params = DiffParams::default();
+        let diff = generate_diff(&repo, "before", "after", &params).unwrap();
+        let lib = &diff.files[0];
+        assert!(lib.hunks.is_empty());
+        assert_eq!((lib.additions, lib.deletions), (0, 0));
+        let submodule = lib.submodule.as_ref().unwrap();
+        let old = submodule.old_commit.as_ref().unwrap();
+        assert_eq!(old.oid, v1.to_string());
+        assert_eq!(old.summary.as_deref(), Some("First"));
+        let new = submodule.new_commit.as_ref().unwrap();
+        assert_eq!(new.summary.as_deref(), Some("Second"));
+        assert!(submodule.files.is_none());
+
+        let summary =
+            diff_summary(&repo, "before", "after", &params, &mut DiffCache::new()).unwrap();
+        assert_eq!(summary.files[0].additions, 0);
+
+        params.settings.recurse_submodules = true;
+        let diff = generate_diff(&repo, "before", "after", &params).unwrap();
+        let nested = diff.files[0]
+            .submodule
+            .as_ref()
+            .and_then(|s| s.files.as_ref())
+            .unwrap();
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].path, "lib.rs");
+        assert_eq!((nested[0].additions, nested[0].deletions), (1, 0));
+    }
+
+    #[test]
+    fn test_generate_diff_resolves_lfs_pointers() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let store = |content: &[u8]| {
+            let oid = git2::Oid::hash_object(git2::ObjectType::Blob, content)
+                .unwrap()
+                .to_string();
+            // Any 64-digit hex name will do for the test.
+            let oid = format!("{}{}", oid, &oid[..24]);
+            let path = lfs::object_path(&repo, &oid);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                oid,
+                content.len()
+            )
+        };
+        let old = store(b"one\ntwo\n");
+        let new = store(b"one\nthree\n");
+        let missing = "version https://git-lfs.github.com/spec/v1\noid sha256:\
+            2222222222222222222222222222222222222222222222222222222222222222\nsize 99\n";
+        commit_entries(&repo, "before", &[("data.txt", old.as_bytes(), 0o100644)]);
+        commit_entries(
+            &repo,
+            "after",
+            &[
+                ("data.txt", new.as_bytes(), 0o100644),
+                ("model.bin", missing.as_bytes(), 0o100644),
+            ],
+        );
+
+        let diff = generate_diff(&repo, "before", "after", &DiffParams::default()).unwrap();
+        let data = diff.files.iter().find(|f| f.path == "data.txt").unwrap();
+        let lfs = data.lfs.as_ref().unwrap();
+        assert!(lfs.old.as_ref().unwrap().present && lfs.new.as_ref().unwrap().present);
+        assert_eq!((data.additions, data.deletions), (1, 1));
+        let lines: Vec<&str> = data.hunks[0]
+            .lines
+            .iter()
+            .map(|l| l.content.as_str())
+            .collect();
+        assert_eq!(lines, vec!["one", "two", "three"]);
+
+        let model = diff.files.iter().find(|f| f.path == "model.bin").unwrap();
+        let pointer = model.lfs.as_ref().unwrap().new.as_ref().unwrap();
+        assert_eq!(pointer.size, 99);
+        assert_eq!(model.new_size, Some(99));
+        assert!(!pointer.present);
+        assert!(model.hunks.is_empty() && model.additions == 0);
+
+        let summary = diff_summary(
+            &repo,
+            "before",
+            "after",
+            &DiffParams::default(),
+            &mut DiffCache::new(),
+        )
+        .unwrap();
+        assert!(summary.files.iter().all(|f| f.additions == 0));
+    }
+
+    #[test]
+    fn test_generate_diff_binary_metadata_and_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let png_v1 = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x01";
+        let png_v2 = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x02\x00";
+        fs::write(dir.path().join("logo.png"), png_v1).unwrap();
+        make_commit(&repo, dir.path(), "add logo");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("logo.png"), png_v2).unwrap();
+        make_commit(&repo, dir.path(), "update logo");
+
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
+        let logo = &diff.files[0];
+        assert!(logo.binary);
+        assert!(logo.hunks.is_empty());
+        assert_eq!(logo.mime_type.as_deref(), Some("image/png"));
+        assert_eq!(logo.old_size, Some(png_v1.len() as u64));
+        assert_eq!(logo.new_size, Some(png_v2.len() as u64));
+
+        let pair = blob_pair(
+            &repo,
+            "feature",
+            &logo.path,
+            None,
+            logo.old_oid.as_deref(),
+            logo.new_oid.as_deref(),
+        )
+        .unwrap();
+        let decode = |b: &BlobContent| {
+            base64::engine::general_purpose::STANDARD
+                .decode(&b.data)
+                .unwrap()
+        };
+        assert_eq!(decode(pair.old.as_ref().unwrap()), png_v1);
+        assert_eq!(decode(pair.new.as_ref().unwrap()), png_v2);
+    }
+
+    #[test]
+    fn test_blob_pair_reads_untracked_workdir_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        fs::write(dir.path().join("new.gif"), b"GIF89a\x01\x00").unwrap();
+
+        let params = DiffParams {
+            mode: CompareMode::Workdir,
+            ..Default::default()
+        };
+        let diff = generate_diff(&repo, "main", "", &params).unwrap();
+        let gif = diff.files.iter().find(|f| f.path == "new.gif").unwrap();
+        assert!(gif.old_oid.is_none());
+        assert_eq!(gif.mime_type.as_deref(), Some("image/gif"));
+
+        let pair = blob_pair(&repo, "", "new.gif", None, None, gif.new_oid.as_deref()).unwrap();
+        assert!(pair.old.is_none());
+        assert_eq!(pair.new.unwrap().size, 8);
+
+        fs::write(dir.path().join("new.gif"), b"GIF89a\x02\x00").unwrap();
+        assert!(blob_pair(&repo, "", "new.gif", None, None, gif.new_oid.as_deref()).is_err());
+    }
+
+    #[test]
+    fn test_generate_diff_decodes_legacy_encodings() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        fs::write(dir.path().join("latin1.txt"), b"na\xefve\ncaf\xe9\n").unwrap();
+        let utf16 = |s: &str| -> Vec<u8> {
+            let mut bytes = vec![0xff, 0xfe];
+            bytes.extend(s.encode_utf16().flat_map(|u| u.to_le_bytes()));
+            bytes
+        };
+        fs::write(dir.path().join("wide.txt"), utf16("one\ntwo\n")).unwrap();
+        make_commit(&repo, dir.path(), "legacy files");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("latin1.txt"), b"na\xefve\ncr\xe8me\n").unwrap();
+        fs::write(dir.path().join("wide.txt"), utf16("one\nzwei\n")).unwrap();
+        make_commit(&repo, dir.path(), "edit legacy files");
+
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
+        let file = |path: &str| diff.files.iter().find(|f| f.path == path).unwrap();
+        let added = |f: &DiffFile| -> Vec<String> {
+            f.hunks
+                .iter()
+                .flat_map(|h| &h.lines)
+                .filter(|l| matches!(l.line_type, LineType::Add))
+                .map(|l| l.content.clone())
+                .collect()
+        };
+
+        let latin1 = file("latin1.txt");
+        assert_eq!(latin1.encoding.as_deref(), Some("windows-1252"));
+        assert!(!latin1.lossy);
+        assert_eq!(added(latin1), vec!["crème"]);
+
+        let wide = file("wide.txt");
+        assert!(!wide.binary);
+        assert_eq!(wide.encoding.as_deref(), Some("UTF-16LE"));
+        assert_eq!(added(wide), vec!["zwei"]);
+    }
+
+    #[test]
+    fn test_generate_diff_eof_newline_and_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        fs::write(dir.path().join("eof.txt"), "a\nb").unwrap();
+        fs::write(dir.path().join("dos.txt"), "one\r\ntwo\r\n").unwrap();
+        make_commit(&repo, dir.path(), "add files");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("eof.txt"), "a\nb\n").unwrap();
+        fs::write(dir.path().join("dos.txt"), "one\ntwo\n").unwrap();
+        make_commit(&repo, dir.path(), "normalize");
+
+        let diff = generate_diff(&repo, "main", "feature", &DiffParams::default()).unwrap();
+        let file = |path: &str| diff.files.iter().find(|f| f.path == path).unwrap();
+
+        let eof = file("eof.txt");
+        assert_eq!(eof.eof_newline, Some(EofNewline::Added));
+        let lines: Vec<_> = eof.hunks.iter().flat_map(|h| &h.lines).collect();
+        assert!(lines.iter().all(|l| l.content == "a" || l.content == "b"));
+        let removed = lines
+            .iter()
+            .find(|l| matches!(l.line_type, LineType::Delete))
+            .unwrap();
+        assert!(removed.no_newline);
+
+        let dos = file("dos.txt");
+        let endings = dos.line_endings.as_ref().unwrap();
+        assert_eq!(endings.from, LineEnding::Crlf);
+        assert_eq!(endings.to, LineEnding::Lf);
+        assert!(dos.hunks.is_empty());
+    }
+
+    #[test]
+    fn test_generate_diff_applies_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let body: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        fs::write(dir.path().join("file.txt"), &body).unwrap();
+        make_commit(&repo, dir.path(), "add file");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        let changed = body
+            .replace("line 2\n", "line  2\n")
+            .replace("line 10\n", "line ten\n");
+        fs::write(dir.path().join("file.txt"), changed).unwrap();
+        make_commit(&repo, dir.path(), "edit");
+
+        let hunks = |settings: DiffSettings| {
+            let params = DiffParams {
+                settings,
+                ..Default::default()
+            };
+            let diff = generate_diff(&repo, "main", "feature", &params).unwrap();
+            diff.files[0].hunks.clone()
+        };
+
+        let default = hunks(DiffSettings::default());
+        assert_eq!(default.len(), 2);
+
+        let ignored = hunks(DiffSettings {
+            ignore_whitespace_change: true,
+            context_lines: 1,
+            ..Default::default()
+        });
+        assert_eq!(ignored.len(), 1);
+        assert_eq!(ignored[0].old_start, 9);
+        assert_eq!(ignored[0].lines.len(), 4);
+
+        let merged = hunks(DiffSettings {
+            interhunk_lines: 10,
+            ..Default::default()
+        });
+        assert_eq!(merged.len(), 1);
+    }
+
+    #[test]
+    fn test_generate_diff_applies_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        commit_on_branch(&repo, dir.path(), "feature");
+        let lines = |n: usize| -> String { (1..=n).map(|i| format!("line {}\n", i)).collect() };
+        fs::write(dir.path().join("a.txt"), lines(15)).unwrap();
+        fs::write(dir.path().join("b.txt"), lines(15)).unwrap();
+        fs::write(dir.path().join("big.txt"), "x".repeat(2000)).unwrap();
+        fs::write(dir.path().join("many.txt"), lines(30)).unwrap();
+        fs::write(dir.path().join("wide.txt"), format!("{}\n", "y".repeat(50))).unwrap();
+        make_commit(&repo, dir.path(), "add files");
+
+        let params = DiffParams {
+            settings: DiffSettings {
+                limits: DiffLimits {
+                    max_file_size: 1000,
+                    max_file_lines: 20,
+                    max_total_lines: 25,
+                    max_line_length: 10,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let diff = generate_diff(&repo, "main", "feature", &params).unwrap();
+        let file = |path: &str| diff.files.iter().find(|f| f.path == path).unwrap();
+
+        assert!(file("a.txt").truncated.is_empty());
+        assert_eq!(file("a.txt").hunks[0].lines.len(), 15);
+        assert_eq!(file("b.txt").truncated, vec![TruncationReason::TotalLines]);
+        assert!(file("b.txt").hunks.is_empty());
+        assert_eq!(file("big.txt").truncated, vec![TruncationReason::FileSize]);
+        assert!(file("big.txt").hunks.is_empty());
+        assert_eq!(file("big.txt").new_size, Some(2000));
+        // Counts still cover the whole file.
+        assert_eq!(
+            file("many.txt").truncated,
+            vec![TruncationReason::FileLines]
+        );
+        assert_eq!(file("many.txt").additions, 30);
+        // Lines the limits dropped still count towards the total.
+        assert_eq!(
+            file("wide.txt").truncated,
+            vec![TruncationReason::TotalLines]
+        );
+
+        let mut cache = DiffCache::new();
+        let wide = file_diff(
+            &repo, "main", "feature", &params, "wide.txt", None, 0, &mut cache,
+        )
+        .unwrap();
+        assert_eq!(wide.truncated, vec![TruncationReason::LineLength]);
+        assert_eq!(wide.hunks[0].lines[0].content, "y".repeat(10));
+
+        let unlimited = DiffParams {
+            settings: DiffSettings {
+                limits: DiffLimits::unlimited(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let many = file_diff(
+            &repo, "main", "feature", &unlimited, "many.txt", None, 0, &mut cache,
+        )
+        .unwrap();
+        assert!(many.truncated.is_empty());
+        assert_eq!(many.hunks[0].lines.len(), 30);
+
+        // Loaded on its own, a file still counts the lines listed before it.
+        let b = file_diff(
+            &repo, "main", "feature", &params, "b.txt", None, 15, &mut cache,
+        )
+        .unwrap();
+        assert_eq!(b.truncated, vec![TruncationReason::TotalLines]);
+        assert_eq!(b.additions, 15);
+
+        // The same file with nothing before it isn't served the cut-off copy,
+        // and vice versa.
+        let first = file_diff(
+            &repo, "main", "feature", &params, "b.txt", None, 0, &mut cache,
+        )
+        .unwrap();
+        assert!(first.truncated.is_empty());
+        assert_eq!(first.hunks[0].lines.len(), 15);
+        let b = file_diff(
+            &repo, "main", "feature", &params, "b.txt", None, 15, &mut cache,
+        )
+        .unwrap();
+        assert_eq!(b.truncated, vec![TruncationReason::TotalLines]);
+    }
+
+    #[test]
+    fn test_diff_summary_and_file_diff_use_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let body: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        fs::write(dir.path().join("old.rs"), &body).unwrap();
+        fs::write(dir.path().join("edit.rs"), "a\nb\n").unwrap();
+        make_commit(&repo, dir.path(), "add sources");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::remove_file(dir.path().join("old.rs")).unwrap();
+        fs::write(dir.path().join("new.rs"), format!("{}line 21\n", body)).unwrap();
+        fs::write(dir.path().join("edit.rs"), "a\nc\nd\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("old.rs")).unwrap();
+        index.write().unwrap();
+        make_commit(&repo, dir.path(), "move and edit");
+
+        let mut cache = DiffCache::new();
+        let params = DiffParams::default();
+        let summary = diff_summary(&repo, "main", "feature", &params, &mut cache).unwrap();
+        assert_eq!(summary.files.len(), 2);
+        let edit = summary.files.iter().find(|f| f.path == "edit.rs").unwrap();
+        assert_eq!((edit.additions, edit.deletions), (2, 1));
+        let rename = summary.files.iter().find(|f| f.path == "new.rs").unwrap();
+        assert_eq!(rename.old_path.as_deref(), Some("old.rs"));
+        assert_eq!((rename.additions, rename.deletions), (1, 0));
+
+        let file = file_diff(
+            &repo,
+            "main",
+            "feature",
+            &params,
+            "new.rs",
+            rename.old_path.as_deref(),
+            0,
+            &mut cache,
+        )
+        .unwrap();
+        assert!(matches!(file.status, FileStatus::Renamed));
+        assert_eq!(file.hunks.len(), 1);
+
+        // Served from the cache even once the branch has moved on, since the
+        // key is the pair of trees rather than the refs.
+        let feature_tree = repo.revparse_single("feature^{tree}").unwrap().id();
+        fs::write(dir.path().join("edit.rs"), "changed again\n").unwrap();
+        make_commit(&repo, dir.path(), "more");
+        let key = DiffKey {
+            from_tree: repo.revparse_single("main^{tree}").unwrap().id(),
+            to_tree: feature_tree,
+            settings: DiffSettings::default(),
+        };
+        assert!(cache
+            .entry(key)
+            .file_diffs
+            .contains_key(&("new.rs".to_string(), 0)));
+        let moved = diff_summary(&repo, "main", "feature", &params, &mut cache).unwrap();
+        let edit = moved.files.iter().find(|f| f.path == "edit.rs").unwrap();
+        assert_eq!((edit.additions, edit.deletions), (1, 2));
+
+        let missing = file_diff(
+            &repo, "main", "feature", &params, "nope.rs", None, 0, &mut cache,
+        );
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_blob_lines_and_file_at_ref() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        let body: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        fs::write(dir.path().join("file.txt"), &body).unwrap();
+        make_commit(&repo, dir.path(), "add file");
+
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("file.txt"), body.replace("line 9", "nine")).unwrap();
+        make_commit(&repo, dir.path(), "edit");
+
+        let params = DiffParams {
+            settings: DiffSettings {
+                context_lines: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let diff = generate_diff(&repo, "main", "feature", &params).unwrap();
+        let file = &diff.files[0];
+        assert_eq!(file.hunks[0].old_start, 9);
+
+        // Expand upwards from the hunk on the old side.
+        let old_oid = file.old_oid.as_deref().unwrap();
+        let above = blob_lines(&repo, "feature", "file.txt", old_oid, 6, Some(8)).unwrap();
+        assert_eq!(above.start, 6);
+        assert_eq!(above.lines, vec!["line 6", "line 7", "line 8"]);
+        assert_eq!(above.total, 10);
+
+        let new_oid = file.new_oid.as_deref().unwrap();
+        let below = blob_lines(&repo, "feature", "file.txt", new_oid, 10, Some(99)).unwrap();
+        assert_eq!(below.lines, vec!["line 10"]);
+
+        let whole = file_at_ref(&repo, "feature", DiffSide::Compare, "file.txt").unwrap();
+        assert_eq!(whole.lines.len(), 10);
+        assert_eq!(whole.lines[8], "nine");
+        assert!(file_at_ref(&repo, "main", DiffSide::Base, "missing.txt").is_err());
+    }
+
+    #[test]
+    fn test_checkout_path_stays_inside() {
+        let workdir = Path::new("/repo");
+        assert_eq!(
+            checkout_path(workdir, "src/./main.rs").unwrap(),
+            workdir.join("src/./main.rs")
+        );
+        for path in ["../secret", "src/../../secret", "/etc/passwd"] {
+            assert!(checkout_path(workdir, path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_list_commits_and_single_commit_diffs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = setup_test_repo(dir.path());
+        commit_on_branch(&repo, dir.path(), "feature");
+        fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+        make_commit(&repo, dir.path(), "add a");
+        fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+        fs::write(dir.path().join("a.txt"), "a2\n").unwrap();
+        make_commit(&repo, dir.path(), "add b\n\nwith a body");
+
+        let commits = list_commits(&repo, "main", "feature").unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].summary, "add a");
+        assert_eq!(commits[0].files, vec!["a.txt"]);
+        assert_eq!(commits[1].summary, "add b");
+        assert_eq!(commits[1].files, vec!["a.txt", "b.txt"]);
+        assert_eq!(commits[1].parents, vec![commits[0].oid.clone()]);
+        assert_eq!(commits[0].author, "Test");
+
+        let range = |from: &str, to: &str| DiffParams {
+            commits: Some(CommitRange {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            ..Default::default()
+        };
+        let first = &commits[0].oid;
+        let second = &commits[1].oid;
+        let single = generate_diff(&repo, "main", "feature", &range(second, second)).unwrap();
+        assert_eq!(single.compare_oid, *second);
+        let paths: Vec<_> = single.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+        assert!(matches!(single.files[0].status, FileStatus::Modified));
+
+        let both = generate_diff(&repo, "main", "feature", &range(first, second)).unwrap();
+        assert!(matches!(both.files[0].status, FileStatus::Added));
+
+        let root = generate_diff(&repo, "main", "feature", &range("main", "main")).unwrap();
+        assert_eq!(root.files.len(), 1);
+        assert!(matches!(root.files[0].status, FileStatus::Added));
+        let backwards = generate_diff(&repo, "main", "feature", &range(second, first));
+        assert!(backwards.unwrap_err().contains("isn't an ancestor"));
+        let missing = generate_diff(&repo, "main", "feature", &range("nope", second));
+        assert!(missing.unwrap_err().contains("commit range start 'nope'"));
+    }
 }
@@ -0,0 +1,245 @@
+use crate::types::{DiffLine, LineType, TextRange};
+
+/// Token-pair budget for the LCS table. Longer line pairs (minified code and
+/// the like) are left without intra-line highlights.
+const MAX_CELLS: usize = 1_000_000;
+
+/// Below this share of unchanged text the lines are treated as unrelated, and
+/// highlighting nearly everything would just be noise.
+const MIN_SIMILARITY: f64 = 0.3;
+
+/// Pair each run of deleted lines with the added lines that follow it and
+/// fill in `changes` on both sides of every pair.
+pub fn annotate(lines: &mut [DiffLine]) {
+    let mut i = 0;
+    while i < lines.len() {
+        let del_start = i;
+        while i < lines.len() && matches!(lines[i].line_type, LineType::Delete) {
+            i += 1;
+        }
+        let add_start = i;
+        while i < lines.len() && matches!(lines[i].line_type, LineType::Add) {
+            i += 1;
+        }
+        if del_start == i {
+            i += 1;
+            continue;
+        }
+
+        let pairs = (add_start - del_start).min(i - add_start);
+        for k in 0..pairs {
+            let (old, new) = (del_start + k, add_start + k);
+            if let Some((old_ranges, new_ranges)) =
+                changed_ranges(&lines[old].content, &lines[new].content)
+            {
+                lines[old].changes = old_ranges;
+                lines[new].changes = new_ranges;
+            }
+        }
+    }
+}
+
+/// Ranges of `old` and `new` that differ, or `None` if the lines are too
+/// different (or too long) to be worth highlighting.
+fn changed_ranges(old: &str, new: &str) -> Option<(Vec<TextRange>, Vec<TextRange>)> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+
+    let prefix = old_tokens
+        .iter()
+        .zip(&new_tokens)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_tokens[prefix..]
+        .iter()
+        .rev()
+        .zip(new_tokens[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old_tokens[prefix..old_tokens.len() - suffix];
+    let new_mid = &new_tokens[prefix..new_tokens.len() - suffix];
+    if old_mid.len().saturating_mul(new_mid.len()) > MAX_CELLS {
+        return None;
+    }
+
+    let (old_kept, new_kept) = lcs(old_mid, new_mid);
+    let mut old_changed = vec![false; old_tokens.len()];
+    let mut new_changed = vec![false; new_tokens.len()];
+    for (idx, kept) in old_kept.iter().enumerate() {
+        old_changed[prefix + idx] = !kept;
+    }
+    for (idx, kept) in new_kept.iter().enumerate() {
+        new_changed[prefix + idx] = !kept;
+    }
+
+    let common = weight(
+        old_tokens
+            .iter()
+            .zip(&old_changed)
+            .filter(|(_, changed)| !**changed)
+            .map(|(token, _)| token),
+    );
+    let longest = weight(old_tokens.iter()).max(weight(new_tokens.iter()));
+    if longest > 0 && (common as f64) < MIN_SIMILARITY * longest as f64 {
+        return None;
+    }
+
+    Some((
+        ranges(&old_tokens, &old_changed),
+        ranges(&new_tokens, &new_changed),
+    ))
+}
+
+/// Characters of non-whitespace content in `tokens`.
+fn weight<'a>(tokens: impl Iterator<Item = &'a &'a str>) -> usize {
+    tokens
+        .filter(|token| !token.trim().is_empty())
+        .map(|token| token.chars().count())
+        .sum()
+}
+
+/// Split a line into identifier/number runs, whitespace runs, and single
+/// punctuation characters.
+fn tokenize(line: &str) -> Vec<&str> {
+    #[derive(PartialEq)]
+    enum Class {
+        Word,
+        Space,
+        Punct,
+    }
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            Class::Word
+        } else if c.is_whitespace() {
+            Class::Space
+        } else {
+            Class::Punct
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut current: Option<Class> = None;
+    for (idx, c) in line.char_indices() {
+        let next = class(c);
+        let split = match &current {
+            None => false,
+            Some(Class::Punct) => true,
+            Some(prev) => *prev != next,
+        };
+        if split {
+            tokens.push(&line[start..idx]);
+            start = idx;
+        }
+        current = Some(next);
+    }
+    if start < line.len() {
+        tokens.push(&line[start..]);
+    }
+    tokens
+}
+
+/// Longest common subsequence of two token lists, returned as a "kept" flag
+/// per token on each side.
+fn lcs(old: &[&str], new: &[&str]) -> (Vec<bool>, Vec<bool>) {
+    let (n, m) = (old.len(), new.len());
+    // table[i][j] = LCS length of old[i..] and new[j..]
+    let mut table = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[at(i, j)] = if old[i] == new[j] {
+                table[at(i + 1, j + 1)] + 1
+            } else {
+                table[at(i + 1, j)].max(table[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut old_kept = vec![false; n];
+    let mut new_kept = vec![false; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            old_kept[i] = true;
+            new_kept[j] = true;
+            i += 1;
+            j += 1;
+        } else if table[at(i + 1, j)] >= table[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (old_kept, new_kept)
+}
+
+/// Merge runs of changed tokens into ranges, in UTF-16 code units so they
+/// index directly into JavaScript strings.
+fn ranges(tokens: &[&str], changed: &[bool]) -> Vec<TextRange> {
+    let mut ranges: Vec<TextRange> = Vec::new();
+    let mut offset = 0;
+    for (token, changed) in tokens.iter().zip(changed) {
+        let len = token.encode_utf16().count() as u32;
+        if *changed {
+            match ranges.last_mut() {
+                Some(last) if last.end == offset => last.end += len,
+                _ => ranges.push(TextRange {
+                    start: offset,
+                    end: offset + len,
+                }),
+            }
+        }
+        offset += len;
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(line: &str, ranges: &[TextRange]) -> Vec<String> {
+        let units: Vec<u16> = line.encode_utf16().collect();
+        ranges
+            .iter()
+            .map(|r| String::from_utf16(&units[r.start as usize..r.end as usize]).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_changed_ranges_marks_edited_tokens() {
+        let old = "let total = compute_sum(items, 0);";
+        let new = "let total = compute_total(items, 1);";
+        let (old_ranges, new_ranges) = changed_ranges(old, new).unwrap();
+        assert_eq!(spans(old, &old_ranges), vec!["compute_sum", "0"]);
+        assert_eq!(spans(new, &new_ranges), vec!["compute_total", "1"]);
+
+        assert_eq!(tokenize("a.b(c_1)"), vec!["a", ".", "b", "(", "c_1", ")"]);
+        assert!(changed_ranges("fn alpha() {}", "struct Beta;").is_none());
+    }
+
+    #[test]
+    fn test_annotate_pairs_runs_and_counts_utf16() {
+        let line = |line_type, content: &str| DiffLine {
+            line_type,
+            content: content.to_string(),
+            old_num: None,
+            new_num: None,
+            no_newline: false,
+            changes: Vec::new(),
+        };
+        let mut lines = vec![
+            line(LineType::Context, "ctx"),
+            line(LineType::Delete, "name = \"🦀 ferris\""),
+            line(LineType::Add, "name = \"🦀 corro\""),
+            line(LineType::Add, "extra line"),
+        ];
+        annotate(&mut lines);
+
+        assert!(lines[0].changes.is_empty());
+        assert_eq!(spans(&lines[1].content, &lines[1].changes), vec!["ferris"]);
+        assert_eq!(lines[2].changes, vec![TextRange { start: 11, end: 16 }]);
+        assert!(lines[3].changes.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use git2::Repository;
+
+use crate::types::{LfsChange, LfsPointer};
+
+/// Pointer files are small; git-lfs itself won't parse anything larger.
+const MAX_POINTER_SIZE: usize = 1024;
+
+/// Objects larger than this are never loaded whole, even with the diff
+/// limits lifted.
+pub const MAX_OBJECT_SIZE: u64 = 256 * 1024 * 1024;
+
+const SPEC_VERSIONS: [&str; 2] = [
+    "https://git-lfs.github.com/spec/v1",
+    "https://hawser.github.com/spec/v1",
+];
+
+/// Parse a Git LFS pointer file. `present` is left unset.
+pub fn parse_pointer(content: &[u8]) -> Option<LfsPointer> {
+    if content.len() > MAX_POINTER_SIZE {
+        return None;
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let mut lines = text.lines();
+    let version = lines.next()?.strip_prefix("version ")?;
+    if !SPEC_VERSIONS.contains(&version) {
+        return None;
+    }
+
+    let (mut oid, mut size) = (None, None);
+    for line in lines {
+        if let Some(hash) = line.strip_prefix("oid sha256:") {
+            let valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+            oid = valid.then_some(hash);
+        } else if let Some(bytes) = line.strip_prefix("size ") {
+            size = bytes.parse().ok();
+        }
+    }
+    Some(LfsPointer {
+        oid: oid?.to_string(),
+        size: size?,
+        present: false,
+    })
+}
+
+/// Where git-lfs keeps an object in the repository's local store. Linked
+/// worktrees share the main repository's store.
+pub fn object_path(repo: &Repository, oid: &str) -> PathBuf {
+    repo.commondir()
+        .join("lfs")
+        .join("objects")
+        .join(&oid[..2])
+        .join(&oid[2..4])
+        .join(oid)
+}
+
+/// The pointer stored in blob `oid`, if it is one, with `present` set when
+/// its object is in the local store.
+pub fn blob_pointer(repo: &Repository, oid: &str) -> Option<LfsPointer> {
+    let oid = git2::Oid::from_str(oid).ok()?;
+    // Check the size from the header before loading the blob.
+    let (size, kind) = repo.odb().ok()?.read_header(oid).ok()?;
+    if kind != git2::ObjectType::Blob || size > MAX_POINTER_SIZE {
+        return None;
+    }
+    let blob = repo.find_blob(oid).ok()?;
+    let mut pointer = parse_pointer(blob.content())?;
+    pointer.present = object_path(repo, &pointer.oid)
+        .metadata()
+        .is_ok_and(|m| m.len() == pointer.size);
+    Some(pointer)
+}
+
+/// LFS pointers on either side of a change. `None` when neither side is one.
+/// Working-directory files are checked out content, never pointers, so only
+/// blobs in the object database are looked at.
+pub fn pointer_change(
+    repo: &Repository,
+    old_oid: Option<&str>,
+    new_oid: Option<&str>,
+) -> Option<LfsChange> {
+    let old = old_oid.and_then(|oid| blob_pointer(repo, oid));
+    let new = new_oid.and_then(|oid| blob_pointer(repo, oid));
+    (old.is_some() || new.is_some()).then_some(LfsChange { old, new })
+}
+
+/// The first `len` bytes of a pointer's object, for sniffing without reading
+/// the whole thing.
+pub fn object_head(repo: &Repository, pointer: &LfsPointer, len: usize) -> Option<Vec<u8>> {
+    let file = std::fs::File::open(object_path(repo, &pointer.oid)).ok()?;
+    let mut head = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut head).ok()?;
+    Some(head)
+}
+
+/// Swap pointer-file content for the object it points to, when that object
+/// is in the local store and no larger than [`MAX_OBJECT_SIZE`]. Anything
+/// else is returned unchanged.
+pub fn resolve(repo: &Repository, content: Vec<u8>) -> Vec<u8> {
+    let Some(pointer) = parse_pointer(&content).filter(|p| p.size <= MAX_OBJECT_SIZE) else {
+        return content;
+    };
+    match std::fs::read(object_path(repo, &pointer.oid)) {
+        Ok(object) if object.len() as u64 == pointer.size => object,
+        _ => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pointer_text(oid: &str, size: usize) -> String {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            oid, size
+        )
+    }
+
+    #[test]
+    fn test_parse_pointer() {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let pointer = parse_pointer(pointer_text(oid, 12345).as_bytes()).unwrap();
+        assert_eq!(pointer.oid, oid);
+        assert_eq!(pointer.size, 12345);
+
+        assert!(
+            parse_pointer(b"version https://example.com/v1\noid sha256:00\nsize 1\n").is_none()
+        );
+        assert!(parse_pointer(pointer_text("abc", 1).as_bytes()).is_none());
+        assert!(parse_pointer(b"just some text\n").is_none());
+    }
+
+    #[test]
+    fn test_blob_pointer_and_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let content = b"the real content\n";
+        let oid = "1111111111111111111111111111111111111111111111111111111111111111";
+        let text = pointer_text(oid, content.len());
+        let blob = repo.blob(text.as_bytes()).unwrap().to_string();
+
+        let missing = blob_pointer(&repo, &blob).unwrap();
+        assert!(!missing.present);
+        assert_eq!(resolve(&repo, text.clone().into_bytes()), text.as_bytes());
+
+        let path = object_path(&repo, oid);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        let pointer = blob_pointer(&repo, &blob).unwrap();
+        assert!(pointer.present);
+        assert_eq!(object_head(&repo, &pointer, 8).unwrap(), &content[..8]);
+        assert_eq!(resolve(&repo, text.into_bytes()), content);
+
+        let plain = repo.blob(b"not a pointer\n").unwrap().to_string();
+        assert!(pointer_change(&repo, Some(&plain), Some(&plain)).is_none());
+        let change = pointer_change(&repo, Some(&plain), Some(&blob)).unwrap();
+        assert!(change.old.is_none() && change.new.is_some());
+    }
+}
@@ -1,7 +1,21 @@
+mod cache;
 mod commands;
+mod curate;
+mod dirdiff;
+mod encoding;
+mod export;
 mod git;
+mod intraline;
+mod lfs;
 mod mcp;
+mod merge;
+mod mime;
+mod patchfile;
+mod rangediff;
+mod rounds;
+mod settings;
 mod state;
+mod stats;
 mod types;
 
 use state::AppState;
@@ -10,6 +24,7 @@ use state::AppState;
 pub fn run() {
     let app_state = AppState::new();
     let queue_for_mcp = app_state.comment_queue.clone();
+    let repo_for_mcp = app_state.repo_path.clone();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
@@ -18,12 +33,30 @@ pub fn run() {
             commands::open_repo,
             commands::get_refs,
             commands::get_diff,
+            commands::get_diff_summary,
+            commands::get_file_diff,
+            commands::list_commits,
+            commands::record_review_round,
+            commands::list_review_rounds,
+            commands::get_range_diff,
+            commands::get_merge_preview,
+            commands::merge_into_base,
+            commands::apply_selection,
+            commands::export_patch,
+            commands::export_mbox,
+            commands::open_patch_file,
+            commands::compare_paths,
+            commands::get_blob_pair,
+            commands::get_blob_lines,
+            commands::get_file_at_ref,
+            commands::get_diff_settings,
+            commands::set_diff_settings,
             commands::submit_comment,
             commands::get_queue_length,
         ])
         .setup(|_app| {
             tauri::async_runtime::spawn(async move {
-                if let Err(e) = mcp::start_mcp_server(queue_for_mcp, 3100).await {
+                if let Err(e) = mcp::start_mcp_server(queue_for_mcp, repo_for_mcp, 3100).await {
                     eprintln!("MCP server error: {}", e);
                 }
             });
@@ -2,24 +2,37 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 
 use rmcp::{
-    handler::server::router::tool::ToolRouter,
+    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
     model::{ServerCapabilities, ServerInfo},
-    tool, tool_handler, tool_router, ServerHandler,
+    schemars, tool, tool_handler, tool_router, ServerHandler,
 };
+use serde::Deserialize;
 
+use crate::git;
+use crate::merge;
 use crate::state::CommentQueue;
 
 #[derive(Clone)]
 pub struct DifferMcpServer {
     queue: Arc<Mutex<CommentQueue>>,
+    repo_path: Arc<Mutex<Option<String>>>,
     tool_router: ToolRouter<Self>,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct MergePreviewRequest {
+    /// Branch, tag or commit the changes would be merged into.
+    pub base: String,
+    /// Branch, tag, commit or `worktree:<name>` being reviewed.
+    pub compare: String,
+}
+
 #[tool_router]
 impl DifferMcpServer {
-    pub fn new(queue: Arc<Mutex<CommentQueue>>) -> Self {
+    pub fn new(queue: Arc<Mutex<CommentQueue>>, repo_path: Arc<Mutex<Option<String>>>) -> Self {
         Self {
             queue,
+            repo_path,
             tool_router: Self::tool_router(),
         }
     }
@@ -42,6 +55,24 @@ impl DifferMcpServer {
         let queue = self.queue.lock().await;
         format!("{{\"pending\": {}}}", queue.len())
     }
+
+    #[tool(
+        description = "Check whether compare would merge cleanly into base. Returns whether it is a fast-forward, and for each conflicted path the ours/theirs/ancestor content and the conflict hunks as git would write them."
+    )]
+    async fn preview_merge(&self, Parameters(request): Parameters<MergePreviewRequest>) -> String {
+        let repo_path = self.repo_path.lock().await;
+        let preview = repo_path
+            .as_deref()
+            .ok_or_else(|| "No repo opened".to_string())
+            .and_then(git::discover_repo)
+            .and_then(|repo| merge::merge_preview(&repo, &request.base, &request.compare));
+        match preview {
+            Ok(preview) => {
+                serde_json::to_string_pretty(&preview).unwrap_or_else(|_| "Serialization error".into())
+            }
+            Err(e) => e,
+        }
+    }
 }
 
 #[tool_handler]
@@ -51,7 +82,8 @@ impl ServerHandler for DifferMcpServer {
             instructions: Some(
                 "Differ review tool. Use get_next_comment to receive code review feedback. \
                  Each comment includes a file path, line range, code context, and the reviewer's instruction. \
-                 Process comments one at a time."
+                 Process comments one at a time. Use preview_merge to check for merge conflicts \
+                 before the branch is accepted."
                     .into(),
             ),
             capabilities: ServerCapabilities::builder().enable_tools().build(),
@@ -60,7 +92,11 @@ impl ServerHandler for DifferMcpServer {
     }
 }
 
-pub async fn start_mcp_server(queue: Arc<Mutex<CommentQueue>>, port: u16) -> Result<(), String> {
+pub async fn start_mcp_server(
+    queue: Arc<Mutex<CommentQueue>>,
+    repo_path: Arc<Mutex<Option<String>>>,
+    port: u16,
+) -> Result<(), String> {
     use hyper_util::rt::TokioIo;
     use rmcp::transport::streamable_http_server::{
         session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
@@ -71,7 +107,7 @@ pub async fn start_mcp_server(queue: Arc<Mutex<CommentQueue>>, port: u16) -> Res
 
     let queue_clone = queue.clone();
     let http_service = StreamableHttpService::new(
-        move || Ok(DifferMcpServer::new(queue_clone.clone())),
+        move || Ok(DifferMcpServer::new(queue_clone.clone(), repo_path.clone())),
         session_manager,
         config,
     );
@@ -0,0 +1,624 @@
+use git2::{IndexConflict, IndexEntry, IndexTime, MergeFileOptions, Repository};
+
+use crate::encoding;
+use crate::git;
+use crate::types::{
+    ConflictFile, ConflictHunk, ConflictSide, DiffSide, FileMode, MergeOutcome, MergePreview,
+    MergeStrategy,
+};
+
+/// Width of git's conflict markers.
+const MARKER_LEN: usize = 7;
+
+/// Simulate `git merge <compare>` on top of base with libgit2's in-memory
+/// `merge_trees` and report the paths that would conflict. Nothing is written
+/// to any worktree or index.
+pub fn merge_preview(repo: &Repository, base: &str, compare: &str) -> Result<MergePreview, String> {
+    let base_commit = git::resolve_commit(repo, base, DiffSide::Base)?;
+    let compare_oid = git::compare_head(repo, compare)?;
+    let compare_commit = repo
+        .find_commit(compare_oid)
+        .map_err(|e| format!("Failed to read commit {}: {}", compare_oid, e))?;
+    let merge_base = repo.merge_base(base_commit.id(), compare_oid).ok();
+
+    let up_to_date = merge_base == Some(compare_oid);
+    let mut preview = MergePreview {
+        base_oid: base_commit.id().to_string(),
+        compare_oid: compare_oid.to_string(),
+        merge_base: merge_base.map(|oid| oid.to_string()),
+        up_to_date,
+        fast_forward: !up_to_date && merge_base == Some(base_commit.id()),
+        conflicts: Vec::new(),
+    };
+    if preview.up_to_date || preview.fast_forward {
+        return Ok(preview);
+    }
+
+    let index = merge_index(repo, merge_base, &base_commit, &compare_commit)?;
+    preview.conflicts = index_conflicts(repo, &index, base, compare)?;
+    Ok(preview)
+}
+
+/// Merge compare into the local branch `base` like `git merge` would, and
+/// move base's checkout along if it has one.
+///
+/// Refuses to touch a checkout with uncommitted changes. If the merge
+/// conflicts nothing is written and the conflicts are returned instead.
+pub fn merge_into_base(
+    repo: &Repository,
+    base: &str,
+    compare: &str,
+    strategy: MergeStrategy,
+    message: Option<&str>,
+    delete_compare: bool,
+) -> Result<MergeOutcome, String> {
+    let mut base_ref = repo
+        .find_branch(base, git2::BranchType::Local)
+        .map_err(|_| format!("'{}' is not a local branch", base))?
+        .into_reference();
+    let base_commit = base_ref
+        .peel_to_commit()
+        .map_err(|e| format!("Failed to read '{}': {}", base, e))?;
+    let compare_oid = git::compare_head(repo, compare)?;
+    let compare_commit = repo
+        .find_commit(compare_oid)
+        .map_err(|e| format!("Failed to read commit {}: {}", compare_oid, e))?;
+
+    // Check everything that could stop us before writing anything.
+    let compare_branch = if delete_compare {
+        let branch = repo
+            .find_branch(compare, git2::BranchType::Local)
+            .map_err(|_| {
+                format!(
+                    "'{}' is not a local branch, so it can't be deleted",
+                    compare
+                )
+            })?;
+        if git::branch_checkout(repo, compare)?.is_some() {
+            return Err(format!(
+                "'{}' is checked out, so it can't be deleted",
+                compare
+            ));
+        }
+        Some(branch)
+    } else {
+        None
+    };
+    let checkout = git::branch_checkout(repo, base)?;
+    if let Some(checkout) = &checkout {
+        ensure_clean(checkout, base)?;
+    }
+
+    let merge_base = repo.merge_base(base_commit.id(), compare_oid).ok();
+    if merge_base == Some(compare_oid) {
+        return Err(format!(
+            "'{}' is already up to date with '{}'",
+            base, compare
+        ));
+    }
+
+    let new_tip = match strategy {
+        MergeStrategy::FastForward => {
+            if merge_base != Some(base_commit.id()) {
+                return Err(format!(
+                    "Can't fast-forward '{}': it has commits that aren't in '{}'",
+                    base, compare
+                ));
+            }
+            compare_oid
+        }
+        MergeStrategy::Merge | MergeStrategy::Squash => {
+            let mut index = merge_index(repo, merge_base, &base_commit, &compare_commit)?;
+            let conflicts = index_conflicts(repo, &index, base, compare)?;
+            if !conflicts.is_empty() {
+                return Ok(MergeOutcome {
+                    commit: None,
+                    conflicts,
+                    deleted_branch: false,
+                });
+            }
+
+            let tree = index
+                .write_tree_to(repo)
+                .and_then(|oid| repo.find_tree(oid))
+                .map_err(|e| format!("Failed to write merged tree: {}", e))?;
+            let (parents, default_message) = if strategy == MergeStrategy::Merge {
+                (
+                    vec![&base_commit, &compare_commit],
+                    format!("Merge branch '{}' into {}", compare, base),
+                )
+            } else {
+                (
+                    vec![&base_commit],
+                    squash_message(repo, &base_commit, compare_oid, compare)?,
+                )
+            };
+            let message = message
+                .filter(|m| !m.trim().is_empty())
+                .map_or(default_message, str::to_string);
+            let sig = repo
+                .signature()
+                .map_err(|e| format!("Failed to read git identity: {}", e))?;
+            repo.commit(None, &sig, &sig, &message, &tree, &parents)
+                .map_err(|e| format!("Failed to create commit: {}", e))?
+        }
+    };
+
+    if let Some(checkout) = &checkout {
+        let target = checkout
+            .find_object(new_tip, None)
+            .map_err(|e| format!("Failed to read commit {}: {}", new_tip, e))?;
+        checkout
+            .checkout_tree(&target, Some(git2::build::CheckoutBuilder::new().safe()))
+            .map_err(|e| format!("Failed to update the checkout of '{}': {}", base, e))?;
+    }
+    base_ref
+        .set_target(new_tip, &format!("merge {}", compare))
+        .map_err(|e| format!("Failed to update '{}': {}", base, e))?;
+
+    let deleted_branch = match compare_branch {
+        Some(mut branch) => {
+            branch
+                .delete()
+                .map_err(|e| format!("Merged, but failed to delete '{}': {}", compare, e))?;
+            true
+        }
+        None => false,
+    };
+    Ok(MergeOutcome {
+        commit: Some(new_tip.to_string()),
+        conflicts: Vec::new(),
+        deleted_branch,
+    })
+}
+
+fn ensure_clean(checkout: &Repository, base: &str) -> Result<(), String> {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(false).include_ignored(false);
+    let statuses = checkout
+        .statuses(Some(&mut opts))
+        .map_err(|e| format!("Failed to read status of '{}': {}", base, e))?;
+    if !statuses.is_empty() {
+        return Err(format!(
+            "The checkout of '{}' has uncommitted changes; commit or stash them first",
+            base
+        ));
+    }
+    Ok(())
+}
+
+/// Summaries of the squashed commits, oldest first.
+fn squash_message(
+    repo: &Repository,
+    base_commit: &git2::Commit,
+    compare_oid: git2::Oid,
+    compare: &str,
+) -> Result<String, String> {
+    let mut message = format!("Squashed '{}'\n\n", compare);
+    for commit in git::commits_between(repo, base_commit.id(), compare_oid)? {
+        message.push_str(&format!("* {}\n", commit.summary().unwrap_or("")));
+    }
+    Ok(message)
+}
+
+fn merge_index(
+    repo: &Repository,
+    merge_base: Option<git2::Oid>,
+    base_commit: &git2::Commit,
+    compare_commit: &git2::Commit,
+) -> Result<git2::Index, String> {
+    let ancestor_tree = match merge_base {
+        Some(oid) => repo.find_commit(oid).and_then(|commit| commit.tree()),
+        // Unrelated histories are merged against the empty tree.
+        None => repo
+            .treebuilder(None)
+            .and_then(|builder| builder.write())
+            .and_then(|oid| repo.find_tree(oid)),
+    }
+    .map_err(|e| format!("Failed to read merge base tree: {}", e))?;
+    let base_tree = base_commit
+        .tree()
+        .map_err(|e| format!("Failed to get tree for {}: {}", base_commit.id(), e))?;
+    let compare_tree = compare_commit
+        .tree()
+        .map_err(|e| format!("Failed to get tree for {}: {}", compare_commit.id(), e))?;
+
+    repo.merge_trees(&ancestor_tree, &base_tree, &compare_tree, None)
+        .map_err(|e| format!("Failed to merge trees: {}", e))
+}
+
+fn index_conflicts(
+    repo: &Repository,
+    index: &git2::Index,
+    base: &str,
+    compare: &str,
+) -> Result<Vec<ConflictFile>, String> {
+    let conflicts = index
+        .conflicts()
+        .map_err(|e| format!("Failed to read merge conflicts: {}", e))?;
+    conflicts
+        .map(|conflict| {
+            let conflict = conflict.map_err(|e| format!("Failed to read merge conflict: {}", e))?;
+            conflict_file(repo, &conflict, base, compare)
+        })
+        .collect()
+}
+
+fn conflict_file(
+    repo: &Repository,
+    conflict: &IndexConflict,
+    base: &str,
+    compare: &str,
+) -> Result<ConflictFile, String> {
+    let side = |entry: &Option<IndexEntry>| {
+        entry
+            .as_ref()
+            .map(|entry| conflict_side(repo, entry))
+            .transpose()
+    };
+    let ancestor = side(&conflict.ancestor)?;
+    let ours = side(&conflict.our)?;
+    let theirs = side(&conflict.their)?;
+
+    let sides = [&ours, &theirs, &ancestor];
+    let path = sides
+        .iter()
+        .find_map(|side| side.as_ref().map(|s| s.path.clone()))
+        .unwrap_or_default();
+    let binary = sides
+        .iter()
+        .any(|side| side.as_ref().is_some_and(|s| s.content.is_none()));
+
+    let merged = match (&conflict.our, &conflict.their) {
+        (Some(our), Some(their)) if !binary => {
+            // add/add conflicts have no ancestor; merge against an empty file
+            // like git does.
+            let empty;
+            let ancestor = match &conflict.ancestor {
+                Some(entry) => entry,
+                None => {
+                    empty = empty_entry(repo, our)?;
+                    &empty
+                }
+            };
+            let mut opts = MergeFileOptions::new();
+            opts.style_diff3(true)
+                .ancestor_label("merge base")
+                .our_label(base)
+                .their_label(compare);
+            let result = repo
+                .merge_file_from_index(ancestor, our, their, Some(&mut opts))
+                .map_err(|e| format!("Failed to merge '{}': {}", path, e))?;
+            Some(encoding::decode(result.content(), None).text)
+        }
+        _ => None,
+    };
+    let hunks = merged.as_deref().map(conflict_hunks).unwrap_or_default();
+
+    Ok(ConflictFile {
+        path,
+        ancestor,
+        ours,
+        theirs,
+        binary,
+        merged,
+        hunks,
+    })
+}
+
+fn conflict_side(repo: &Repository, entry: &IndexEntry) -> Result<ConflictSide, String> {
+    let path = String::from_utf8_lossy(&entry.path).into_owned();
+    let mode = git::mode_from_bits(entry.mode);
+    // Submodule entries point at commits, not blobs.
+    let content = if mode == Some(FileMode::Commit) {
+        None
+    } else {
+        let blob = repo
+            .find_blob(entry.id)
+            .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        (!blob.is_binary()).then(|| encoding::decode(blob.content(), None).text)
+    };
+    Ok(ConflictSide {
+        path,
+        oid: entry.id.to_string(),
+        mode,
+        content,
+    })
+}
+
+fn empty_entry(repo: &Repository, like: &IndexEntry) -> Result<IndexEntry, String> {
+    let id = repo
+        .blob(b"")
+        .map_err(|e| format!("Failed to create empty blob: {}", e))?;
+    Ok(IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: like.mode,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id,
+        flags: 0,
+        flags_extended: 0,
+        path: like.path.clone(),
+    })
+}
+
+/// Split diff3-style merge output into its conflicted regions.
+fn conflict_hunks(merged: &str) -> Vec<ConflictHunk> {
+    enum Part {
+        Ours,
+        Ancestor,
+        Theirs,
+    }
+    let is_marker = |line: &str, c: u8| {
+        let bytes = line.as_bytes();
+        bytes.len() >= MARKER_LEN
+            && bytes[..MARKER_LEN].iter().all(|&b| b == c)
+            && bytes.get(MARKER_LEN).is_none_or(|&b| b == b' ')
+    };
+
+    let mut hunks = Vec::new();
+    let mut current: Option<(ConflictHunk, Part)> = None;
+    for (idx, line) in merged.lines().enumerate() {
+        let number = idx as u32 + 1;
+        let Some((hunk, part)) = current.as_mut() else {
+            if is_marker(line, b'<') {
+                let hunk = ConflictHunk {
+                    start: number,
+                    end: number,
+                    ours: Vec::new(),
+                    ancestor: Vec::new(),
+                    theirs: Vec::new(),
+                };
+                current = Some((hunk, Part::Ours));
+            }
+            continue;
+        };
+
+        if is_marker(line, b'|') {
+            *part = Part::Ancestor;
+        } else if is_marker(line, b'=') {
+            *part = Part::Theirs;
+        } else if is_marker(line, b'>') {
+            hunk.end = number;
+            hunks.extend(current.take().map(|(hunk, _)| hunk));
+        } else {
+            let lines = match part {
+                Part::Ours => &mut hunk.ours,
+                Part::Ancestor => &mut hunk.ancestor,
+                Part::Theirs => &mut hunk.theirs,
+            };
+            lines.push(line.to_string());
+        }
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_support::commit;
+
+    fn root(repo: &Repository) -> git2::Oid {
+        commit(repo, None, &[], "root")
+    }
+
+    #[test]
+    fn test_merge_preview_reports_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = root(&repo);
+        let start = commit(
+            &repo,
+            Some(root),
+            &[
+                ("shared.txt", Some("one\ntwo\nthree\n")),
+                ("gone.txt", Some("keep me\n")),
+                ("clean.txt", Some("a\n")),
+            ],
+            "start",
+        );
+        let ours = commit(
+            &repo,
+            Some(start),
+            &[
+                ("shared.txt", Some("one\nTWO\nthree\n")),
+                ("gone.txt", None),
+                ("new.txt", Some("ours\n")),
+            ],
+            "ours",
+        );
+        let theirs = commit(
+            &repo,
+            Some(start),
+            &[
+                ("shared.txt", Some("one\n2\nthree\n")),
+                ("gone.txt", Some("changed\n")),
+                ("new.txt", Some("theirs\n")),
+                ("clean.txt", Some("b\n")),
+            ],
+            "theirs",
+        );
+
+        let preview = merge_preview(&repo, &ours.to_string(), &theirs.to_string()).unwrap();
+        assert!(!preview.up_to_date && !preview.fast_forward);
+        assert_eq!(preview.merge_base, Some(start.to_string()));
+        let paths: Vec<_> = preview.conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["gone.txt", "new.txt", "shared.txt"]);
+
+        let gone = &preview.conflicts[0];
+        assert!(gone.ours.is_none());
+        assert_eq!(
+            gone.theirs.as_ref().unwrap().content.as_deref(),
+            Some("changed\n")
+        );
+        assert!(gone.merged.is_none());
+
+        let added = &preview.conflicts[1];
+        assert!(added.ancestor.is_none());
+        assert_eq!(added.hunks.len(), 1);
+        assert_eq!(added.hunks[0].ours, vec!["ours"]);
+        assert_eq!(added.hunks[0].theirs, vec!["theirs"]);
+
+        let shared = &preview.conflicts[2];
+        assert_eq!(shared.hunks.len(), 1);
+        let hunk = &shared.hunks[0];
+        assert_eq!((hunk.start, hunk.end), (2, 8));
+        assert_eq!(hunk.ours, vec!["TWO"]);
+        assert_eq!(hunk.ancestor, vec!["two"]);
+        assert_eq!(hunk.theirs, vec!["2"]);
+        assert!(shared
+            .merged
+            .as_deref()
+            .unwrap()
+            .starts_with(&format!("one\n<<<<<<< {}\nTWO\n", ours)));
+    }
+
+    #[test]
+    fn test_merge_preview_fast_forward_and_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = root(&repo);
+        let base = commit(&repo, Some(root), &[("a.txt", Some("a\n"))], "base");
+        let ahead = commit(&repo, Some(base), &[("b.txt", Some("b\n"))], "ahead");
+        let other = commit(&repo, Some(base), &[("c.txt", Some("c\n"))], "other");
+
+        let preview = merge_preview(&repo, &base.to_string(), &ahead.to_string()).unwrap();
+        assert!(preview.fast_forward && preview.conflicts.is_empty());
+        let preview = merge_preview(&repo, &ahead.to_string(), &base.to_string()).unwrap();
+        assert!(preview.up_to_date);
+
+        let preview = merge_preview(&repo, &other.to_string(), &ahead.to_string()).unwrap();
+        assert!(!preview.fast_forward && !preview.up_to_date);
+        assert!(preview.conflicts.is_empty());
+    }
+
+    /// A repository with `main` checked out and `feature` branched from it.
+    fn branches(dir: &std::path::Path) -> (Repository, git2::Oid) {
+        let repo = Repository::init(dir).unwrap();
+        {
+            let mut config = repo.config().unwrap();
+            config.set_str("user.name", "Test").unwrap();
+            config.set_str("user.email", "test@test.com").unwrap();
+        }
+        let root = root(&repo);
+        let start = commit(&repo, Some(root), &[("a.txt", Some("a\n"))], "start");
+        repo.branch("main", &repo.find_commit(start).unwrap(), false)
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        let feature = commit(&repo, Some(start), &[("b.txt", Some("b\n"))], "add b");
+        repo.branch("feature", &repo.find_commit(feature).unwrap(), false)
+            .unwrap();
+        (repo, start)
+    }
+
+    #[test]
+    fn test_merge_into_base_fast_forward_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, _) = branches(dir.path());
+
+        let outcome = merge_into_base(
+            &repo,
+            "main",
+            "feature",
+            MergeStrategy::FastForward,
+            None,
+            true,
+        )
+        .unwrap();
+        let feature_tip = outcome.commit.unwrap();
+        assert!(outcome.deleted_branch);
+        assert!(repo
+            .find_branch("feature", git2::BranchType::Local)
+            .is_err());
+        assert_eq!(
+            repo.head().unwrap().target().unwrap().to_string(),
+            feature_tip
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+            "b\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_into_base_merge_squash_and_guards() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, _) = branches(dir.path());
+        let main = repo.head().unwrap().target().unwrap();
+        let other = commit(&repo, Some(main), &[("c.txt", Some("c\n"))], "add c");
+        repo.reference("refs/heads/main", other, true, "test")
+            .unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let err = merge_into_base(
+            &repo,
+            "main",
+            "feature",
+            MergeStrategy::FastForward,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert!(err.contains("Can't fast-forward"));
+
+        std::fs::write(dir.path().join("a.txt"), "dirty\n").unwrap();
+        let err = merge_into_base(&repo, "main", "feature", MergeStrategy::Merge, None, false)
+            .unwrap_err();
+        assert!(err.contains("uncommitted changes"));
+        std::fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+
+        let outcome = merge_into_base(
+            &repo,
+            "main",
+            "feature",
+            MergeStrategy::Squash,
+            Some("Squash feature"),
+            false,
+        )
+        .unwrap();
+        let squashed = repo
+            .find_commit(git2::Oid::from_str(&outcome.commit.unwrap()).unwrap())
+            .unwrap();
+        assert_eq!(squashed.parent_count(), 1);
+        assert_eq!(squashed.message(), Some("Squash feature"));
+        assert!(dir.path().join("b.txt").exists());
+
+        // The squash commit doesn't share history with feature, so merging it
+        // again is a real merge.
+        let outcome =
+            merge_into_base(&repo, "main", "feature", MergeStrategy::Merge, None, false).unwrap();
+        let merged = repo
+            .find_commit(git2::Oid::from_str(&outcome.commit.unwrap()).unwrap())
+            .unwrap();
+        assert_eq!(merged.parent_count(), 2);
+        assert_eq!(merged.message(), Some("Merge branch 'feature' into main"));
+    }
+
+    #[test]
+    fn test_merge_into_base_stops_on_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, start) = branches(dir.path());
+        let ours = commit(&repo, Some(start), &[("a.txt", Some("ours\n"))], "ours");
+        repo.reference("refs/heads/main", ours, true, "test")
+            .unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        let theirs = commit(&repo, Some(start), &[("a.txt", Some("theirs\n"))], "theirs");
+        repo.reference("refs/heads/feature", theirs, true, "test")
+            .unwrap();
+
+        let outcome =
+            merge_into_base(&repo, "main", "feature", MergeStrategy::Merge, None, true).unwrap();
+        assert!(outcome.commit.is_none() && !outcome.deleted_branch);
+        assert_eq!(outcome.conflicts[0].path, "a.txt");
+        assert_eq!(repo.head().unwrap().target(), Some(ours));
+        assert!(repo.find_branch("feature", git2::BranchType::Local).is_ok());
+    }
+}
@@ -0,0 +1,87 @@
+/// Magic-number signatures checked before falling back to the extension.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"BM", "image/bmp"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x00asm", "application/wasm"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("json", "application/json"),
+    ("html", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("md", "text/markdown"),
+    ("xml", "application/xml"),
+];
+
+/// Guess a MIME type from a file's leading bytes, falling back to its
+/// extension. `head` only needs the first few hundred bytes.
+pub fn sniff(path: &str, head: Option<&[u8]>) -> Option<String> {
+    if let Some(head) = head {
+        if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+            return Some("image/webp".into());
+        }
+        if let Some((_, mime)) = SIGNATURES.iter().find(|(sig, _)| head.starts_with(sig)) {
+            return Some(mime.to_string());
+        }
+    }
+
+    let ext = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, mime)| mime.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_prefers_content() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+        assert_eq!(sniff("logo.bin", Some(png)).as_deref(), Some("image/png"));
+        assert_eq!(
+            sniff("x", Some(b"RIFF\x00\x00\x00\x00WEBPVP8 ")).as_deref(),
+            Some("image/webp")
+        );
+    }
+
+    #[test]
+    fn test_sniff_falls_back_to_extension() {
+        assert_eq!(
+            sniff("icons/add.SVG", None).as_deref(),
+            Some("image/svg+xml")
+        );
+        assert_eq!(
+            sniff("data.json", Some(b"{}")).as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(sniff("Makefile", None), None);
+    }
+}
@@ -0,0 +1,841 @@
+use base64::Engine;
+use encoding_rs::Encoding;
+
+use crate::encoding;
+use crate::git;
+use crate::mime;
+use crate::stats;
+use crate::types::{
+    CommitInfo, CompareMode, DiffFile, DiffHunk, DiffLimits, DiffLine, DiffResult, FileMode,
+    FileStatus, LineType, PatchEntry, PatchSeries,
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Read a `.patch`/`.diff` file or a format-patch mbox for review. No
+/// repository is needed; everything shown comes from the file itself.
+pub fn open_patch_file(path: &str, limits: &DiffLimits) -> Result<PatchSeries, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let decoded = encoding::decode(&bytes, None);
+    let mut patches = parse_series(&decoded.text, limits)?;
+    if patches.iter().all(|p| p.diff.files.is_empty()) {
+        return Err(format!("'{}' doesn't contain a diff", path));
+    }
+
+    for file in patches
+        .iter_mut()
+        .flat_map(|p| &mut p.diff.files)
+        .filter(|f| !f.binary)
+    {
+        file.encoding = Some(decoded.encoding.name().to_string());
+        file.lossy = decoded.lossy;
+    }
+    Ok(PatchSeries {
+        path: path.to_string(),
+        patches,
+    })
+}
+
+/// Split an mbox into its mails, or treat the whole text as a single diff if
+/// it has no mail headers.
+pub fn parse_series(text: &str, limits: &DiffLimits) -> Result<Vec<PatchEntry>, String> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let starts: Vec<usize> = (0..lines.len())
+        .filter(|&i| is_mail_start(&lines, i))
+        .collect();
+    if starts.is_empty() {
+        return Ok(vec![PatchEntry {
+            commit: None,
+            message: String::new(),
+            diff: diff_result(parse_diff(&lines, limits)?, ""),
+        }]);
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| {
+            let end = starts.get(n + 1).copied().unwrap_or(lines.len());
+            parse_mail(&lines[start..end], limits)
+        })
+        .collect()
+}
+
+/// An mbox `From <oid> <date>` separator followed by headers, or a bare mail
+/// that opens with a `From:` header.
+fn is_mail_start(lines: &[&str], i: usize) -> bool {
+    let separator = lines[i].starts_with("From ")
+        && header(lines[i]).is_none()
+        && lines.get(i + 1).is_some_and(|l| header(l).is_some());
+    let bare =
+        i == 0 && header(lines[0]).is_some_and(|(name, _)| name.eq_ignore_ascii_case("From"));
+    separator || bare
+}
+
+fn header(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+    Some((name, value.trim()))
+}
+
+fn parse_mail(lines: &[&str], limits: &DiffLimits) -> Result<PatchEntry, String> {
+    let mut i = 0;
+    let mut oid = String::new();
+    if lines[0].starts_with("From ") && header(lines[0]).is_none() {
+        oid = lines[0][5..]
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_string();
+        i = 1;
+    }
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    while let Some(line) = lines.get(i).map(|l| l.trim_end_matches('\r')) {
+        i += 1;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            // A folded continuation of the previous header.
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = header(line) {
+            headers.push((name.to_ascii_lowercase(), value.to_string()));
+        }
+    }
+    let get = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| decode_words(v))
+    };
+
+    // The commit message runs up to the `---` before the diffstat, or
+    // straight into the diff if there is none.
+    let body_start = i;
+    while let Some(line) = lines.get(i).map(|l| l.trim_end_matches('\r')) {
+        if line == "---" || line.starts_with("diff ") || line.starts_with("Index: ") {
+            break;
+        }
+        if line.starts_with("--- ") && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ ")) {
+            break;
+        }
+        i += 1;
+    }
+    let body = lines[body_start..i]
+        .iter()
+        .map(|l| l.trim_end_matches('\r'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let body = body.trim();
+
+    let subject = get("subject").unwrap_or_default();
+    let summary = strip_subject_prefix(&subject).to_string();
+    let message = if body.is_empty() {
+        summary.clone()
+    } else {
+        format!("{}\n\n{}", summary, body)
+    };
+    let from = get("from").unwrap_or_default();
+    let (author, author_email) = match from.split_once('<') {
+        Some((name, email)) => (
+            name.trim().trim_matches('"').to_string(),
+            email.trim_end_matches('>').trim().to_string(),
+        ),
+        None => (from.trim().to_string(), String::new()),
+    };
+
+    let files = parse_diff(&lines[i..], limits)?;
+    let commit = CommitInfo {
+        oid: oid.clone(),
+        summary,
+        author,
+        author_email,
+        time: get("date").and_then(|d| parse_date(&d)).unwrap_or(0),
+        parents: Vec::new(),
+        files: files.iter().map(|f| f.path.clone()).collect(),
+    };
+    Ok(PatchEntry {
+        commit: Some(commit),
+        message,
+        diff: diff_result(files, &oid),
+    })
+}
+
+fn diff_result(files: Vec<DiffFile>, oid: &str) -> DiffResult {
+    let stats = stats::diff_stats(
+        files
+            .iter()
+            .map(|f| (f.path.as_str(), f.additions, f.deletions)),
+    );
+    DiffResult {
+        base_ref: String::new(),
+        compare_ref: String::new(),
+        base_oid: String::new(),
+        compare_oid: oid.to_string(),
+        mode: CompareMode::Tree,
+        merge_base: None,
+        ahead: 0,
+        behind: 0,
+        stats,
+        files,
+    }
+}
+
+/// Drop a leading `[PATCH v2 3/7]`-style tag from a mail subject.
+fn strip_subject_prefix(subject: &str) -> &str {
+    let subject = subject.trim_start();
+    if let Some(end) = subject.strip_prefix('[').and_then(|rest| rest.find(']')) {
+        if subject[..end + 1].to_ascii_uppercase().contains("PATCH") {
+            return subject[end + 2..].trim_start();
+        }
+    }
+    subject
+}
+
+/// Headers of one file's section, collected before its hunks.
+#[derive(Default)]
+struct FileHeader {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    old_mode: Option<FileMode>,
+    new_mode: Option<FileMode>,
+    old_oid: Option<String>,
+    new_oid: Option<String>,
+    status: Option<FileStatus>,
+    similarity: Option<u32>,
+    binary: bool,
+}
+
+impl FileHeader {
+    /// Apply one of `git diff`'s extended header lines.
+    fn extended(&mut self, line: &str) {
+        let mode = |bits: &str| {
+            u32::from_str_radix(bits.trim(), 8)
+                .ok()
+                .and_then(git::mode_from_bits)
+        };
+        if let Some(bits) = line.strip_prefix("old mode ") {
+            self.old_mode = mode(bits);
+        } else if let Some(bits) = line.strip_prefix("new mode ") {
+            self.new_mode = mode(bits);
+        } else if let Some(bits) = line.strip_prefix("deleted file mode ") {
+            self.old_mode = mode(bits);
+            self.status = Some(FileStatus::Deleted);
+        } else if let Some(bits) = line.strip_prefix("new file mode ") {
+            self.new_mode = mode(bits);
+            self.status = Some(FileStatus::Added);
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            self.old_path = Some(unquote(path).to_string());
+            self.status = Some(FileStatus::Renamed);
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            self.new_path = Some(unquote(path).to_string());
+        } else if let Some(path) = line.strip_prefix("copy from ") {
+            self.old_path = Some(unquote(path).to_string());
+            self.status = Some(FileStatus::Copied);
+        } else if let Some(path) = line.strip_prefix("copy to ") {
+            self.new_path = Some(unquote(path).to_string());
+        } else if let Some(score) = line.strip_prefix("similarity index ") {
+            self.similarity = score.trim_end_matches('%').parse().ok();
+        } else if let Some(index) = line.strip_prefix("index ") {
+            let (oids, bits) = index.split_once(' ').unwrap_or((index, ""));
+            if let Some((old, new)) = oids.split_once("..") {
+                let oid = |oid: &str| (!oid.bytes().all(|b| b == b'0')).then(|| oid.to_string());
+                self.old_oid = oid(old);
+                self.new_oid = oid(new);
+            }
+            if !bits.is_empty() {
+                self.old_mode = self.old_mode.or(mode(bits));
+                self.new_mode = self.new_mode.or(mode(bits));
+            }
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            self.binary = true;
+        }
+    }
+
+    /// Apply a `---` or `+++` line. `/dev/null` marks an added or deleted
+    /// file.
+    fn marker(&mut self, raw: &str, old: bool, git_style: bool) {
+        match marker_path(raw, git_style) {
+            Some(path) if old => self.old_path = self.old_path.take().or(Some(path)),
+            Some(path) => self.new_path = self.new_path.take().or(Some(path)),
+            None if old => {
+                self.status.get_or_insert(FileStatus::Added);
+            }
+            None => {
+                self.status.get_or_insert(FileStatus::Deleted);
+            }
+        }
+    }
+
+    fn into_file(
+        self,
+        mut hunks: Vec<DiffHunk>,
+        limits: &DiffLimits,
+        lines_before: usize,
+    ) -> DiffFile {
+        let status = self.status.unwrap_or(FileStatus::Modified);
+        let path = match status {
+            FileStatus::Deleted => self.old_path.clone().or(self.new_path.clone()),
+            _ => self.new_path.clone().or(self.old_path.clone()),
+        }
+        .unwrap_or_default();
+        let old_path = match status {
+            FileStatus::Renamed | FileStatus::Copied => self.old_path,
+            _ => None,
+        };
+        // Plain diffs don't record modes; assume regular files.
+        let old_mode = match status {
+            FileStatus::Added => None,
+            _ => self.old_mode.or(self.new_mode).or(Some(FileMode::Blob)),
+        };
+        let new_mode = match status {
+            FileStatus::Deleted => None,
+            _ => self.new_mode.or(self.old_mode).or(Some(FileMode::Blob)),
+        };
+
+        let lines = || hunks.iter().flat_map(|h| &h.lines);
+        let additions = lines()
+            .filter(|l| matches!(l.line_type, LineType::Add))
+            .count();
+        let deletions = lines()
+            .filter(|l| matches!(l.line_type, LineType::Delete))
+            .count();
+        let mut limit = git::HunkLimit::new(limits, lines_before);
+        git::limit_hunks(&mut hunks, &mut limit);
+        let changes = git::text_changes(hunks, additions, deletions, limit.truncated);
+        DiffFile {
+            mime_type: mime::sniff(&path, None),
+            path,
+            status,
+            old_path,
+            similarity: self.similarity,
+            old_mode,
+            new_mode,
+            old_oid: self.old_oid,
+            new_oid: self.new_oid,
+            old_size: None,
+            new_size: None,
+            binary: self.binary,
+            encoding: (!self.binary).then(|| "UTF-8".to_string()),
+            old_encoding: None,
+            lossy: false,
+            eof_newline: changes.eof_newline,
+            line_endings: changes.line_endings,
+            additions: changes.additions,
+            deletions: changes.deletions,
+            hunks: changes.hunks,
+            submodule: None,
+            lfs: None,
+            truncated: changes.truncated,
+        }
+    }
+}
+
+/// Parse unified diffs, with or without `git diff`'s extended headers.
+/// Anything between file sections (diffstats, commit text, `Only in` lines)
+/// is skipped.
+fn parse_diff(lines: &[&str], limits: &DiffLimits) -> Result<Vec<DiffFile>, String> {
+    let mut files = Vec::new();
+    let mut lines_before = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\r');
+        let mut header = FileHeader::default();
+        let git_style = line.starts_with("diff --git ");
+
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            (header.old_path, header.new_path) = git_header_paths(paths);
+            i += 1;
+            while let Some(line) = lines.get(i).map(|l| l.trim_end_matches('\r')) {
+                if line.starts_with("diff ") || line.starts_with("@@ ") || line.starts_with("--- ")
+                {
+                    break;
+                }
+                header.extended(line);
+                i += 1;
+            }
+        } else if !is_marker_pair(lines, i) {
+            i += 1;
+            continue;
+        }
+
+        if is_marker_pair(lines, i) {
+            header.marker(&lines[i][4..], true, git_style);
+            header.marker(&lines[i + 1][4..], false, git_style);
+            i += 2;
+        }
+        let (hunks, next) = parse_hunks(lines, i)?;
+        i = next;
+        let file = header.into_file(hunks, limits, lines_before);
+        lines_before += file.additions + file.deletions;
+        files.push(file);
+    }
+    Ok(files)
+}
+
+fn is_marker_pair(lines: &[&str], i: usize) -> bool {
+    lines.get(i).is_some_and(|l| l.starts_with("--- "))
+        && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+}
+
+/// Old and new paths from `diff --git a/<old> b/<new>`. Only unambiguous
+/// when the paths have no spaces or are equal; the `---`/`+++` and rename
+/// lines fill in the rest.
+fn git_header_paths(paths: &str) -> (Option<String>, Option<String>) {
+    let paths = paths.trim_end_matches('\r');
+    if let Some((old, new)) = paths
+        .strip_prefix("a/")
+        .and_then(|rest| rest.split_once(" b/"))
+    {
+        return (Some(old.to_string()), Some(new.to_string()));
+    }
+    // --no-prefix output: "path path".
+    let mid = paths.len() / 2;
+    if paths.len() % 2 == 1 && paths.as_bytes()[mid] == b' ' && paths[..mid] == paths[mid + 1..] {
+        return (
+            Some(paths[..mid].to_string()),
+            Some(paths[..mid].to_string()),
+        );
+    }
+    (None, None)
+}
+
+/// Path from a `---`/`+++` line, minus any timestamp and the leading
+/// directory (`a/`, `b/`, or the first component of a plain diff, like
+/// `patch -p1`). `None` for `/dev/null`.
+fn marker_path(raw: &str, git_style: bool) -> Option<String> {
+    let path = raw.split('\t').next().unwrap_or(raw).trim_end_matches('\r');
+    let path = unquote(path);
+    if path == "/dev/null" {
+        return None;
+    }
+    let stripped = if git_style {
+        path.strip_prefix("a/").or_else(|| path.strip_prefix("b/"))
+    } else {
+        path.split_once('/').map(|(_, rest)| rest)
+    };
+    Some(stripped.unwrap_or(path).to_string())
+}
+
+fn unquote(path: &str) -> &str {
+    path.strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(path)
+}
+
+/// Parse consecutive hunks starting at `i`, returning them and the index of
+/// the first line after them. Hunk bodies are read by the counts in their
+/// headers, so trailing text like a mail signature isn't mistaken for lines.
+fn parse_hunks(lines: &[&str], mut i: usize) -> Result<(Vec<DiffHunk>, usize), String> {
+    let mut hunks = Vec::new();
+    while let Some(line) = lines.get(i).filter(|l| l.starts_with("@@ ")) {
+        let (old_start, old_lines, new_start, new_lines) =
+            hunk_header(line).ok_or_else(|| format!("Malformed hunk header: {}", line))?;
+        i += 1;
+
+        let (mut old_left, mut new_left) = (old_lines, new_lines);
+        let (mut old_num, mut new_num) = (old_start, new_start);
+        let mut body: Vec<DiffLine> = Vec::new();
+        while old_left > 0 || new_left > 0 {
+            let Some(raw) = lines.get(i) else {
+                break;
+            };
+            let line_type = match raw.bytes().next() {
+                // Some tools strip the space off empty context lines.
+                Some(b' ') | None => LineType::Context,
+                Some(b'-') => LineType::Delete,
+                Some(b'+') => LineType::Add,
+                Some(b'\\') => {
+                    if let Some(last) = body.last_mut() {
+                        last.no_newline = true;
+                    }
+                    i += 1;
+                    continue;
+                }
+                _ => break,
+            };
+            let (old, new) = match line_type {
+                LineType::Context => (Some(old_num), Some(new_num)),
+                LineType::Delete => (Some(old_num), None),
+                LineType::Add => (None, Some(new_num)),
+            };
+            if old.is_some() {
+                old_num += 1;
+                old_left = old_left.saturating_sub(1);
+            }
+            if new.is_some() {
+                new_num += 1;
+                new_left = new_left.saturating_sub(1);
+            }
+            body.push(DiffLine {
+                line_type,
+                content: raw.get(1..).unwrap_or("").to_string(),
+                old_num: old,
+                new_num: new,
+                no_newline: false,
+                changes: Vec::new(),
+            });
+            i += 1;
+        }
+        // The marker for the hunk's last line follows the counted lines.
+        if lines.get(i).is_some_and(|l| l.starts_with('\\')) {
+            if let Some(last) = body.last_mut() {
+                last.no_newline = true;
+            }
+            i += 1;
+        }
+
+        hunks.push(DiffHunk {
+            old_start,
+            old_lines,
+            new_start,
+            new_lines,
+            lines: body,
+        });
+    }
+    Ok((hunks, i))
+}
+
+/// `@@ -a,b +c,d @@ ...` into (a, b, c, d). A missing count means 1.
+fn hunk_header(line: &str) -> Option<(u32, u32, u32, u32)> {
+    let ranges = line.strip_prefix("@@ -")?.split_once(" @@")?.0;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |r: &str| -> Option<(u32, u32)> {
+        match r.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((r.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_lines) = range(old)?;
+    let (new_start, new_lines) = range(new)?;
+    Some((old_start, old_lines, new_start, new_lines))
+}
+
+/// Decode RFC 2047 encoded words (`=?UTF-8?q?Ren=C3=A9?=`), which
+/// format-patch uses for non-ASCII names and subjects.
+fn decode_words(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match encoded_word(candidate) {
+            Some((text, len)) => {
+                // Whitespace between adjacent encoded words isn't part of
+                // the text.
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&text);
+                rest = &candidate[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode the encoded word at the start of `s`, returning its text and
+/// length.
+fn encoded_word(s: &str) -> Option<(String, usize)> {
+    let (charset, rest) = s.strip_prefix("=?")?.split_once('?')?;
+    let (kind, rest) = rest.split_once('?')?;
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    let bytes = match kind {
+        "Q" | "q" => q_decode(text)?,
+        "B" | "b" => base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .ok()?,
+        _ => return None,
+    };
+    let encoding = Encoding::for_label(charset.as_bytes())?;
+    let len = 2 + charset.len() + 1 + kind.len() + 1 + end + 2;
+    Some((
+        encoding.decode_without_bom_handling(&bytes).0.into_owned(),
+        len,
+    ))
+}
+
+fn q_decode(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => out.push(b' '),
+            b'=' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    Some(out)
+}
+
+/// Parse an RFC 2822 date such as `Mon, 15 Jan 2024 10:20:30 +0100` into
+/// seconds since the Unix epoch.
+fn parse_date(value: &str) -> Option<i64> {
+    let mut parts = value.split_whitespace().peekable();
+    if parts.peek()?.ends_with(',') {
+        parts.next();
+    }
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS
+        .iter()
+        .position(|m| m.eq_ignore_ascii_case(month_name))? as i64
+        + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|p| p.parse::<i64>().ok());
+    let hours = time.next()??;
+    let minutes = time.next()??;
+    let seconds = time.next().flatten().unwrap_or(0);
+
+    let zone = parts.next().unwrap_or("+0000");
+    let sign = if zone.starts_with('-') { -1 } else { 1 };
+    let digits: i64 = zone.trim_start_matches(['+', '-']).parse().unwrap_or(0);
+    let offset = sign * ((digits / 100) * 3600 + (digits % 100) * 60);
+
+    Some(days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_diff_headers() {
+        let text = "\
+diff --git a/old name.txt b/new name.txt
+similarity index 90%
+rename from old name.txt
+rename to new name.txt
+index 1111111..2222222 100644
+--- a/old name.txt
++++ b/new name.txt
+@@ -1,2 +1,2 @@
+ keep
+-before
++after
+\\ No newline at end of file
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/logo.png b/logo.png
+new file mode 100644
+index 0000000..3333333
+Binary files /dev/null and b/logo.png differ
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+index 4444444..0000000
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+---
+";
+        let entries = parse_series(text, &DiffLimits::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].commit.is_none());
+        let files = &entries[0].diff.files;
+        assert_eq!(files.len(), 4);
+
+        let renamed = &files[0];
+        assert!(matches!(renamed.status, FileStatus::Renamed));
+        assert_eq!(renamed.path, "new name.txt");
+        assert_eq!(renamed.old_path.as_deref(), Some("old name.txt"));
+        assert_eq!(renamed.similarity, Some(90));
+        assert_eq!(renamed.old_oid.as_deref(), Some("1111111"));
+        assert_eq!((renamed.additions, renamed.deletions), (1, 1));
+        let last = renamed.hunks[0].lines.last().unwrap();
+        assert_eq!(last.content, "after");
+        assert!(last.no_newline);
+
+        let script = &files[1];
+        assert_eq!(script.old_mode, Some(FileMode::Blob));
+        assert_eq!(script.new_mode, Some(FileMode::BlobExecutable));
+        assert!(script.hunks.is_empty());
+
+        let logo = &files[2];
+        assert!(matches!(logo.status, FileStatus::Added));
+        assert!(logo.binary && logo.old_oid.is_none());
+        assert_eq!(logo.mime_type.as_deref(), Some("image/png"));
+
+        // The deleted line reads "---", which must not be taken for a
+        // new marker or a mail signature.
+        let gone = &files[3];
+        assert!(matches!(gone.status, FileStatus::Deleted));
+        assert_eq!(gone.path, "gone.txt");
+        assert_eq!(gone.hunks[0].lines[0].content, "--");
+        assert_eq!(entries[0].diff.stats.deletions, 2);
+    }
+
+    #[test]
+    fn test_parse_git_diff_ending_in_header_only_file() {
+        let mode_only = "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n";
+        let files = &parse_series(mode_only, &DiffLimits::default()).unwrap()[0]
+            .diff
+            .files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].new_mode, Some(FileMode::BlobExecutable));
+        assert!(files[0].hunks.is_empty());
+
+        let binary = "\
+diff --git a/logo.png b/logo.png
+index 3333333..5555555 100644
+Binary files a/logo.png and b/logo.png differ
+";
+        let files = &parse_series(binary, &DiffLimits::default()).unwrap()[0]
+            .diff
+            .files;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].binary);
+
+        let literal = "\
+diff --git a/logo.png b/logo.png
+index 3333333..5555555 100644
+GIT binary patch
+literal 4
+LcmZ?wbhEa~
+
+";
+        let files = &parse_series(literal, &DiffLimits::default()).unwrap()[0]
+            .diff
+            .files;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].binary);
+    }
+
+    #[test]
+    fn test_git_header_paths_without_prefix() {
+        assert_eq!(
+            git_header_paths("notes.md notes.md"),
+            (Some("notes.md".to_string()), Some("notes.md".to_string()))
+        );
+        // Odd length with a multi-byte character straddling the midpoint.
+        assert_eq!(git_header_paths("aé"), (None, None));
+        assert_eq!(git_header_paths("éaé"), (None, None));
+        assert!(parse_series("diff --git aé\n", &DiffLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_plain_unified_diff() {
+        let text = "\
+Only in new: extra.txt
+--- old/config.ini\t2024-01-15 10:00:00.000000000 +0100
++++ new/config.ini\t2024-01-15 11:00:00.000000000 +0100
+@@ -1,3 +1,3 @@
+ [server]
+-port = 80
++port = 8080
+
+";
+        let files = &parse_series(text, &DiffLimits::default()).unwrap()[0]
+            .diff
+            .files;
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert_eq!(file.path, "config.ini");
+        assert!(matches!(file.status, FileStatus::Modified));
+        let lines = &file.hunks[0].lines;
+        assert_eq!(lines.len(), 4);
+        assert_eq!((lines[3].old_num, lines[3].new_num), (Some(3), Some(3)));
+        assert!(!lines[2].changes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_format_patch_mbox() {
+        let text = "\
+From 1234567890abcdef1234567890abcdef12345678 Mon Sep 17 00:00:00 2001
+From: =?UTF-8?q?Ren=C3=A9=20Dupont?= <rene@example.com>
+Date: Mon, 15 Jan 2024 10:20:30 +0100
+Subject: [PATCH 1/2] Add a greeting that is long enough
+ to be folded
+
+Explain the change.
+---
+ hello.txt | 1 +
+ 1 file changed, 1 insertion(+)
+
+diff --git a/hello.txt b/hello.txt
+new file mode 100644
+index 0000000..ce01362
+--- /dev/null
++++ b/hello.txt
+@@ -0,0 +1 @@
++hello
+--
+2.43.0
+
+From abcdefabcdefabcdefabcdefabcdefabcdefabcd Mon Sep 17 00:00:00 2001
+From: Test <test@test.com>
+Date: Tue, 16 Jan 2024 08:00:00 -0500
+Subject: [PATCH 2/2] Say goodbye
+
+---
+diff --git a/hello.txt b/hello.txt
+--- a/hello.txt
++++ b/hello.txt
+@@ -1 +1,2 @@
+ hello
++goodbye
+--
+2.43.0
+";
+        let entries = parse_series(text, &DiffLimits::default()).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let first = entries[0].commit.as_ref().unwrap();
+        assert_eq!(first.oid, "1234567890abcdef1234567890abcdef12345678");
+        assert_eq!(first.author, "René Dupont");
+        assert_eq!(first.author_email, "rene@example.com");
+        assert_eq!(
+            first.summary,
+            "Add a greeting that is long enough to be folded"
+        );
+        assert_eq!(first.time, 1705310430);
+        assert_eq!(first.files, vec!["hello.txt"]);
+        assert_eq!(
+            entries[0].message,
+            "Add a greeting that is long enough to be folded\n\nExplain the change."
+        );
+        assert!(matches!(entries[0].diff.files[0].status, FileStatus::Added));
+
+        let second = &entries[1];
+        assert_eq!(second.commit.as_ref().unwrap().summary, "Say goodbye");
+        assert_eq!(
+            second.diff.compare_oid,
+            "abcdefabcdefabcdefabcdefabcdefabcdefabcd"
+        );
+        let lines = &second.diff.files[0].hunks[0].lines;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].content, "goodbye");
+    }
+}
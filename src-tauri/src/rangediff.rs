@@ -0,0 +1,266 @@
+use git2::Repository;
+
+use crate::git;
+use crate::intraline;
+use crate::types::{CommitInfo, CommitPair, DiffLimits, DiffSide, PairStatus};
+
+/// How different two commits' patches may be and still count as the same
+/// commit, as a share of their average size. Matches `git range-diff`'s
+/// default `--creation-factor`.
+const CREATION_FACTOR: f64 = 0.6;
+
+struct SeriesCommit {
+    info: CommitInfo,
+    patch: String,
+}
+
+/// Match the commits of an old and a new version of a branch, like
+/// `git range-diff base..old base..new`, and diff the patches of commits that
+/// changed.
+///
+/// `old` is typically the commit recorded for an earlier review round and
+/// `new` the current compare spec.
+pub fn range_diff(
+    repo: &Repository,
+    base: &str,
+    old: &str,
+    new: &str,
+) -> Result<Vec<CommitPair>, String> {
+    let base = git::resolve_commit(repo, base, DiffSide::Base)?.id();
+    let old_tip = git::resolve_commit(repo, old, git::SpecRole::Round)?.id();
+    let new_tip = git::compare_head(repo, new)?;
+    let old = series(repo, base, old_tip)?;
+    let new = series(repo, base, new_tip)?;
+
+    let mut old_match: Vec<Option<usize>> = vec![None; old.len()];
+    let mut new_match: Vec<Option<usize>> = vec![None; new.len()];
+
+    // Identical patches first, then the closest remaining pairs.
+    for (j, n) in new.iter().enumerate() {
+        if let Some(i) = (0..old.len()).find(|&i| old_match[i].is_none() && old[i].patch == n.patch)
+        {
+            old_match[i] = Some(j);
+            new_match[j] = Some(i);
+        }
+    }
+    let mut candidates = Vec::new();
+    for (i, o) in old
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| old_match[*i].is_none())
+    {
+        for (j, n) in new
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| new_match[*j].is_none())
+        {
+            let cost = changed_lines(&o.patch, &n.patch)?;
+            let size = (o.patch.lines().count() + n.patch.lines().count()) as f64 / 2.0;
+            if (cost as f64) < CREATION_FACTOR * size {
+                candidates.push((cost, i, j));
+            }
+        }
+    }
+    candidates.sort();
+    for (_, i, j) in candidates {
+        if old_match[i].is_none() && new_match[j].is_none() {
+            old_match[i] = Some(j);
+            new_match[j] = Some(i);
+        }
+    }
+
+    // Follow the new series, slotting dropped commits in where they used to be.
+    let mut old = old.into_iter().map(Some).collect::<Vec<_>>();
+    let mut pairs = Vec::new();
+    let mut next_old = 0;
+    for (j, n) in new.into_iter().enumerate() {
+        if let Some(i) = new_match[j] {
+            for k in next_old..i {
+                if old_match[k].is_none() {
+                    pairs.push(removed(old[k].take().unwrap()));
+                }
+            }
+            next_old = next_old.max(i + 1);
+            let o = old[i].take().unwrap();
+            pairs.push(matched(o, n)?);
+        } else {
+            pairs.push(CommitPair {
+                status: PairStatus::Added,
+                old: None,
+                new: Some(n.info),
+                hunks: Vec::new(),
+            });
+        }
+    }
+    pairs.extend(old.into_iter().flatten().map(removed));
+    Ok(pairs)
+}
+
+fn series(repo: &Repository, base: git2::Oid, tip: git2::Oid) -> Result<Vec<SeriesCommit>, String> {
+    git::commits_between(repo, base, tip)?
+        .iter()
+        .map(|commit| {
+            Ok(SeriesCommit {
+                info: git::commit_info(repo, commit)?,
+                patch: patch_text(repo, commit)?,
+            })
+        })
+        .collect()
+}
+
+fn removed(o: SeriesCommit) -> CommitPair {
+    CommitPair {
+        status: PairStatus::Removed,
+        old: Some(o.info),
+        new: None,
+        hunks: Vec::new(),
+    }
+}
+
+fn matched(o: SeriesCommit, n: SeriesCommit) -> Result<CommitPair, String> {
+    if o.patch == n.patch {
+        return Ok(CommitPair {
+            status: PairStatus::Unchanged,
+            old: Some(o.info),
+            new: Some(n.info),
+            hunks: Vec::new(),
+        });
+    }
+
+    let patch = git2::Patch::from_buffers(o.patch.as_bytes(), None, n.patch.as_bytes(), None, None)
+        .map_err(|e| format!("Failed to compare patches: {}", e))?;
+    let unlimited = DiffLimits::unlimited();
+    let mut limit = git::HunkLimit::new(&unlimited, 0);
+    let mut hunks = git::patch_hunks(
+        &patch,
+        |b| Some(String::from_utf8_lossy(b).into_owned()),
+        &mut limit,
+    )
+    .unwrap_or_default();
+    for hunk in &mut hunks {
+        intraline::annotate(&mut hunk.lines);
+    }
+    Ok(CommitPair {
+        status: PairStatus::Modified,
+        old: Some(o.info),
+        new: Some(n.info),
+        hunks,
+    })
+}
+
+fn changed_lines(old: &str, new: &str) -> Result<usize, String> {
+    let patch = git2::Patch::from_buffers(old.as_bytes(), None, new.as_bytes(), None, None)
+        .map_err(|e| format!("Failed to compare patches: {}", e))?;
+    let (_, additions, deletions) = patch
+        .line_stats()
+        .map_err(|e| format!("Failed to compare patches: {}", e))?;
+    Ok(additions + deletions)
+}
+
+/// A commit's message and patch against its first parent, with the parts that
+/// change on every rebase (blob OIDs, hunk line numbers) left out.
+fn patch_text(repo: &Repository, commit: &git2::Commit) -> Result<String, String> {
+    let parent_tree = commit.parent(0).and_then(|p| p.tree()).ok();
+    let diff = commit
+        .tree()
+        .and_then(|tree| repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None))
+        .map_err(|e| format!("Failed to diff commit {}: {}", commit.id(), e))?;
+
+    let mut text = String::from("## Commit message ##\n");
+    for line in commit.message().unwrap_or_default().lines() {
+        text.push_str("    ");
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        let content = String::from_utf8_lossy(line.content());
+        match line.origin() {
+            '+' | '-' | ' ' => {
+                text.push(line.origin());
+                text.push_str(&content);
+                if !content.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            'F' => {
+                for header in content.lines().filter(|l| !l.starts_with("index ")) {
+                    text.push_str(header);
+                    text.push('\n');
+                }
+            }
+            'H' => {
+                // "@@ -1,2 +1,3 @@ fn name" -> "@@ fn name"
+                let context = content.splitn(3, "@@").nth(2).unwrap_or_default();
+                text.push_str("@@");
+                text.push_str(context.trim_end());
+                text.push('\n');
+            }
+            _ => {}
+        }
+        true
+    })
+    .map_err(|e| format!("Failed to print commit {}: {}", commit.id(), e))?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_support::commit;
+
+    #[test]
+    fn test_range_diff_matches_rewritten_series() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, None, &[], "base");
+
+        let body: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let a = commit(&repo, Some(base), &[("a.txt", Some("alpha\n"))], "add a");
+        let b = commit(&repo, Some(a), &[("b.txt", Some(&body))], "add b");
+        let old = commit(&repo, Some(b), &[("c.txt", Some("gamma\n"))], "add c");
+
+        let a2 = commit(&repo, Some(base), &[("a.txt", Some("alpha\n"))], "add a");
+        let b2 = commit(
+            &repo,
+            Some(a2),
+            &[("b.txt", Some(&body.replace("line 5", "line five")))],
+            "add b",
+        );
+        let new = commit(&repo, Some(b2), &[("d.txt", Some("delta\n"))], "add d");
+
+        let pairs =
+            range_diff(&repo, &base.to_string(), &old.to_string(), &new.to_string()).unwrap();
+        let summary: Vec<_> = pairs
+            .iter()
+            .map(|p| {
+                let commit = p.new.as_ref().or(p.old.as_ref()).unwrap();
+                (p.status, commit.summary.as_str())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (PairStatus::Unchanged, "add a"),
+                (PairStatus::Modified, "add b"),
+                (PairStatus::Added, "add d"),
+                (PairStatus::Removed, "add c"),
+            ]
+        );
+
+        let changed: Vec<_> = pairs[1]
+            .hunks
+            .iter()
+            .flat_map(|h| &h.lines)
+            .filter(|l| !matches!(l.line_type, crate::types::LineType::Context))
+            .map(|l| l.content.as_str())
+            .collect();
+        assert_eq!(changed, vec!["+line 5", "+line five"]);
+        let err = range_diff(&repo, &base.to_string(), "nope", &new.to_string()).unwrap_err();
+        assert!(
+            err.starts_with("Failed to resolve review round 'nope'"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use git2::Repository;
+
+use crate::git;
+use crate::types::ReviewRound;
+
+/// Rounds are stored as refs rather than in config so the reviewed commits
+/// stay reachable, and can still be compared, after the branch is rewritten.
+const ROUNDS_PREFIX: &str = "refs/differ/rounds/";
+
+/// Where `compare`'s rounds live. The spec is hex-encoded, since revspecs
+/// like `HEAD~1` or `worktree:<name>` aren't valid ref names, and a spec
+/// can't be used as a path anyway without `foo/1` clashing with round 1 of
+/// `foo`.
+fn round_prefix(compare: &str) -> String {
+    let key: String = compare.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}/", ROUNDS_PREFIX, key)
+}
+
+/// Remember what `compare` points at now as the next review round. Recording
+/// the same commit twice in a row returns the existing round.
+pub fn record_round(repo: &Repository, compare: &str) -> Result<ReviewRound, String> {
+    let oid = git::compare_head(repo, compare)?;
+    let rounds = list_rounds(repo, compare)?;
+    if let Some(last) = rounds.last().filter(|r| r.oid == oid.to_string()) {
+        return Ok(last.clone());
+    }
+
+    let number = rounds.last().map_or(1, |r| r.number + 1);
+    let name = format!("{}{}", round_prefix(compare), number);
+    repo.reference(&name, oid, false, "differ: record review round")
+        .map_err(|e| format!("Failed to record review round: {}", e))?;
+    Ok(ReviewRound {
+        number,
+        oid: oid.to_string(),
+    })
+}
+
+/// Recorded rounds for `compare`, oldest first.
+pub fn list_rounds(repo: &Repository, compare: &str) -> Result<Vec<ReviewRound>, String> {
+    let prefix = round_prefix(compare);
+    let refs = repo
+        .references_glob(&format!("{}*", prefix))
+        .map_err(|e| format!("Failed to list review rounds: {}", e))?;
+
+    let mut rounds = Vec::new();
+    for reference in refs.flatten() {
+        let number = reference
+            .name()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let (Some(number), Some(oid)) = (number, reference.target()) {
+            rounds.push(ReviewRound {
+                number,
+                oid: oid.to_string(),
+            });
+        }
+    }
+    rounds.sort_by_key(|r| r.number);
+    Ok(rounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_support::commit_to_head;
+
+    fn commit_file(repo: &Repository, content: &str) -> git2::Oid {
+        commit_to_head(repo, &[("file.txt", Some(content))], content)
+    }
+
+    #[test]
+    fn test_record_and_list_rounds() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let first = commit_file(&repo, "one");
+        assert!(list_rounds(&repo, "HEAD").unwrap().is_empty());
+
+        let round = record_round(&repo, "HEAD").unwrap();
+        assert_eq!((round.number, round.oid.clone()), (1, first.to_string()));
+        assert_eq!(record_round(&repo, "HEAD").unwrap().number, 1);
+
+        let second = commit_file(&repo, "two");
+        record_round(&repo, "HEAD").unwrap();
+        let rounds = list_rounds(&repo, "HEAD").unwrap();
+        assert_eq!(rounds.len(), 2);
+        assert_eq!(rounds[1].oid, second.to_string());
+        assert!(repo.find_reference("refs/differ/rounds/48454144/1").is_ok());
+    }
+
+    #[test]
+    fn test_rounds_for_specs_that_are_not_ref_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let first = commit_file(&repo, "one");
+        let second = commit_file(&repo, "two");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("foo", head.as_object(), false)
+            .unwrap();
+        repo.branch("foo/1", &repo.find_commit(first).unwrap(), false)
+            .unwrap();
+
+        for spec in ["HEAD~1", "foo^", "foo@{0}"] {
+            let round = record_round(&repo, spec).unwrap();
+            assert_eq!(round.number, 1, "{}", spec);
+        }
+        assert_eq!(
+            list_rounds(&repo, "HEAD~1").unwrap()[0].oid,
+            first.to_string()
+        );
+        assert_eq!(
+            list_rounds(&repo, "foo@{0}").unwrap()[0].oid,
+            second.to_string()
+        );
+
+        // A branch under another ref's name doesn't collide with its rounds.
+        record_round(&repo, "foo").unwrap();
+        record_round(&repo, "foo/1").unwrap();
+        assert_eq!(list_rounds(&repo, "foo").unwrap().len(), 1);
+        assert_eq!(
+            list_rounds(&repo, "foo/1").unwrap()[0].oid,
+            first.to_string()
+        );
+
+        assert_ne!(
+            round_prefix("worktree:agent"),
+            round_prefix("worktree/agent")
+        );
+    }
+}
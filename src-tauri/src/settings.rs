@@ -0,0 +1,136 @@
+use git2::{Config, ConfigLevel, Repository};
+
+use crate::types::{DiffAlgorithm, DiffLimits, DiffSettings, RenameSettings};
+
+/// Diff settings are stored in the repository's own git config under
+/// `differ.*`, so they follow the repo rather than the machine. Values set at
+/// the global level act as defaults.
+pub fn load_diff_settings(repo: &Repository) -> Result<DiffSettings, String> {
+    let config = repo
+        .config()
+        .and_then(|mut c| c.snapshot())
+        .map_err(|e| format!("Failed to read git config: {}", e))?;
+    let defaults = DiffSettings::default();
+    let bool_or = |key: &str, default: bool| config.get_bool(key).unwrap_or(default);
+    let int_or = |key: &str, default: i64| config.get_i64(key).unwrap_or(default);
+
+    let algorithm = match config.get_str("differ.algorithm") {
+        Ok("minimal") => DiffAlgorithm::Minimal,
+        Ok("patience") => DiffAlgorithm::Patience,
+        _ => DiffAlgorithm::Myers,
+    };
+    let renames = &defaults.renames;
+    let limits = &defaults.limits;
+
+    Ok(DiffSettings {
+        ignore_whitespace: bool_or("differ.ignoreWhitespace", defaults.ignore_whitespace),
+        ignore_whitespace_change: bool_or(
+            "differ.ignoreWhitespaceChange",
+            defaults.ignore_whitespace_change,
+        ),
+        ignore_whitespace_eol: bool_or(
+            "differ.ignoreWhitespaceEol",
+            defaults.ignore_whitespace_eol,
+        ),
+        context_lines: int_or("differ.contextLines", defaults.context_lines.into())
+            .try_into()
+            .unwrap_or(defaults.context_lines),
+        interhunk_lines: int_or("differ.interhunkLines", defaults.interhunk_lines.into())
+            .try_into()
+            .unwrap_or(defaults.interhunk_lines),
+        algorithm,
+        indent_heuristic: bool_or("differ.indentHeuristic", defaults.indent_heuristic),
+        renames: RenameSettings {
+            renames: bool_or("differ.renames", renames.renames),
+            copies: bool_or("differ.copies", renames.copies),
+            threshold: int_or("differ.renameThreshold", renames.threshold.into())
+                .try_into()
+                .unwrap_or(renames.threshold),
+            limit: int_or("differ.renameLimit", renames.limit as i64)
+                .try_into()
+                .unwrap_or(renames.limit),
+        },
+        recurse_submodules: bool_or("differ.recurseSubmodules", defaults.recurse_submodules),
+        limits: DiffLimits {
+            max_file_size: int_or("differ.maxFileSize", limits.max_file_size as i64)
+                .try_into()
+                .unwrap_or(limits.max_file_size),
+            max_file_lines: int_or("differ.maxFileLines", limits.max_file_lines as i64)
+                .try_into()
+                .unwrap_or(limits.max_file_lines),
+            max_total_lines: int_or("differ.maxTotalLines", limits.max_total_lines as i64)
+                .try_into()
+                .unwrap_or(limits.max_total_lines),
+            max_line_length: int_or("differ.maxLineLength", limits.max_line_length as i64)
+                .try_into()
+                .unwrap_or(limits.max_line_length),
+        },
+    })
+}
+
+pub fn save_diff_settings(repo: &Repository, settings: &DiffSettings) -> Result<(), String> {
+    let mut config = repo
+        .config()
+        .and_then(|c| c.open_level(ConfigLevel::Local))
+        .map_err(|e| format!("Failed to open repository config: {}", e))?;
+    write_settings(&mut config, settings)
+        .map_err(|e| format!("Failed to save diff settings: {}", e))
+}
+
+fn write_settings(config: &mut Config, settings: &DiffSettings) -> Result<(), git2::Error> {
+    let algorithm = match settings.algorithm {
+        DiffAlgorithm::Myers => "myers",
+        DiffAlgorithm::Minimal => "minimal",
+        DiffAlgorithm::Patience => "patience",
+    };
+    config.set_bool("differ.ignoreWhitespace", settings.ignore_whitespace)?;
+    config.set_bool(
+        "differ.ignoreWhitespaceChange",
+        settings.ignore_whitespace_change,
+    )?;
+    config.set_bool("differ.ignoreWhitespaceEol", settings.ignore_whitespace_eol)?;
+    config.set_i64("differ.contextLines", settings.context_lines.into())?;
+    config.set_i64("differ.interhunkLines", settings.interhunk_lines.into())?;
+    config.set_str("differ.algorithm", algorithm)?;
+    config.set_bool("differ.indentHeuristic", settings.indent_heuristic)?;
+    config.set_bool("differ.renames", settings.renames.renames)?;
+    config.set_bool("differ.copies", settings.renames.copies)?;
+    config.set_i64("differ.renameThreshold", settings.renames.threshold.into())?;
+    config.set_i64("differ.renameLimit", settings.renames.limit as i64)?;
+    config.set_bool("differ.recurseSubmodules", settings.recurse_submodules)?;
+    let limits = &settings.limits;
+    config.set_i64("differ.maxFileSize", limits.max_file_size as i64)?;
+    config.set_i64("differ.maxFileLines", limits.max_file_lines as i64)?;
+    config.set_i64("differ.maxTotalLines", limits.max_total_lines as i64)?;
+    config.set_i64("differ.maxLineLength", limits.max_line_length as i64)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        assert_eq!(load_diff_settings(&repo).unwrap(), DiffSettings::default());
+
+        let mut settings = DiffSettings {
+            ignore_whitespace_change: true,
+            context_lines: 8,
+            algorithm: DiffAlgorithm::Patience,
+            indent_heuristic: false,
+            recurse_submodules: true,
+            ..Default::default()
+        };
+        settings.renames.threshold = 70;
+        settings.limits.max_file_lines = 500;
+        settings.limits.max_line_length = 0;
+        save_diff_settings(&repo, &settings).unwrap();
+
+        let reopened = Repository::open(dir.path()).unwrap();
+        assert_eq!(load_diff_settings(&reopened).unwrap(), settings);
+    }
+}
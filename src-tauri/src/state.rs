@@ -3,6 +3,7 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::cache::DiffCache;
 use crate::types::ReviewComment;
 
 #[derive(Debug)]
@@ -51,6 +52,7 @@ impl CommentQueue {
 pub struct AppState {
     pub comment_queue: Arc<Mutex<CommentQueue>>,
     pub repo_path: Arc<Mutex<Option<String>>>,
+    pub diff_cache: Arc<Mutex<DiffCache>>,
 }
 
 impl AppState {
@@ -58,6 +60,7 @@ impl AppState {
         Self {
             comment_queue: Arc::new(Mutex::new(CommentQueue::new())),
             repo_path: Arc::new(Mutex::new(None)),
+            diff_cache: Arc::new(Mutex::new(DiffCache::new())),
         }
     }
 }
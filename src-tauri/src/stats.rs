@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use crate::types::{DiffStats, DirStats};
+
+/// Totals for a changeset plus the same numbers rolled up per directory,
+/// like `git diff --dirstat` but with raw line counts. Takes each file's
+/// path, additions and deletions.
+pub fn diff_stats<'a>(files: impl IntoIterator<Item = (&'a str, usize, usize)>) -> DiffStats {
+    let mut root = Node::default();
+    for (path, additions, deletions) in files {
+        let mut node = &mut root;
+        node.add(additions, deletions);
+        let dirs = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        for part in dirs.split('/').filter(|part| !part.is_empty()) {
+            node = node.children.entry(part.to_string()).or_default();
+            node.add(additions, deletions);
+        }
+    }
+
+    DiffStats {
+        files: root.files,
+        additions: root.additions,
+        deletions: root.deletions,
+        tree: root.into_dir_stats(String::new()),
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    files: usize,
+    additions: usize,
+    deletions: usize,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn add(&mut self, additions: usize, deletions: usize) {
+        self.files += 1;
+        self.additions += additions;
+        self.deletions += deletions;
+    }
+
+    fn into_dir_stats(self, path: String) -> DirStats {
+        let children = self
+            .children
+            .into_iter()
+            .map(|(name, child)| {
+                let child_path = if path.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", path, name)
+                };
+                child.into_dir_stats(child_path)
+            })
+            .collect();
+        DirStats {
+            path,
+            files: self.files,
+            additions: self.additions,
+            deletions: self.deletions,
+            children,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_stats_rolls_up_directories() {
+        let stats = diff_stats([
+            ("README.md", 1, 0),
+            ("src/lib.rs", 10, 2),
+            ("src/git/diff.rs", 5, 5),
+            ("src/git/refs.rs", 0, 3),
+        ]);
+        assert_eq!((stats.files, stats.additions, stats.deletions), (4, 16, 10));
+
+        let tree = &stats.tree;
+        assert_eq!(tree.path, "");
+        assert_eq!(tree.children.len(), 1);
+        let src = &tree.children[0];
+        assert_eq!(
+            (src.path.as_str(), src.files, src.additions),
+            ("src", 3, 15)
+        );
+        let git = &src.children[0];
+        assert_eq!(git.path, "src/git");
+        assert_eq!((git.files, git.additions, git.deletions), (2, 5, 8));
+        assert!(git.children.is_empty());
+    }
+}
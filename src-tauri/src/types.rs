@@ -5,16 +5,318 @@ use serde::{Deserialize, Serialize};
 pub struct DiffResult {
     pub base_ref: String,
     pub compare_ref: String,
+    pub base_oid: String,
+    pub compare_oid: String,
+    pub mode: CompareMode,
+    /// Merge-base of base and compare, if their histories share one.
+    pub merge_base: Option<String>,
+    /// Commits reachable from compare but not base.
+    pub ahead: usize,
+    /// Commits reachable from base but not compare.
+    pub behind: usize,
+    pub stats: DiffStats,
     pub files: Vec<DiffFile>,
 }
 
+/// A diff's file list without hunks, cheap to send even for changesets with
+/// thousands of files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffSummary {
+    pub base_ref: String,
+    pub compare_ref: String,
+    pub base_oid: String,
+    pub compare_oid: String,
+    pub mode: CompareMode,
+    pub merge_base: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub stats: DiffStats,
+    pub files: Vec<FileSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSummary {
+    pub path: String,
+    pub status: FileStatus,
+    pub old_path: Option<String>,
+    pub similarity: Option<u32>,
+    pub old_mode: Option<FileMode>,
+    pub new_mode: Option<FileMode>,
+    pub old_oid: Option<String>,
+    pub new_oid: Option<String>,
+    /// Added and deleted line counts; zero for binary files.
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffStats {
+    pub files: usize,
+    pub additions: usize,
+    pub deletions: usize,
+    /// The same totals per directory, rooted at the repository root.
+    pub tree: DirStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirStats {
+    /// Directory path relative to the repository root; empty for the root.
+    pub path: String,
+    /// Changed files anywhere below this directory.
+    pub files: usize,
+    pub additions: usize,
+    pub deletions: usize,
+    pub children: Vec<DirStats>,
+}
+
+/// How a diff is computed, beyond which two sides are compared.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiffParams {
+    pub mode: CompareMode,
+    /// Diff from the merge-base of base and compare ("three-dot") instead of
+    /// base itself.
+    pub merge_base: bool,
+    /// Diff a run of commits from the commit list instead of base against
+    /// compare. Takes precedence over `mode` and `merge_base`.
+    pub commits: Option<CommitRange>,
+    pub settings: DiffSettings,
+}
+
+/// A sub-range of the commits between base and compare, both ends
+/// inclusive. `from == to` selects a single commit, diffed against its first
+/// parent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitRange {
+    /// The oldest commit in the range.
+    pub from: String,
+    /// The newest commit in the range.
+    pub to: String,
+}
+
+/// Reviewer preferences for how diffs are rendered. Persisted per repository.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiffSettings {
+    pub ignore_whitespace: bool,
+    pub ignore_whitespace_change: bool,
+    pub ignore_whitespace_eol: bool,
+    pub context_lines: u32,
+    /// Hunks separated by at most this many lines are merged into one.
+    pub interhunk_lines: u32,
+    pub algorithm: DiffAlgorithm,
+    pub indent_heuristic: bool,
+    pub renames: RenameSettings,
+    /// Include a changed submodule's own diff under its entry.
+    pub recurse_submodules: bool,
+    /// How much of a large diff to load.
+    pub limits: DiffLimits,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        Self {
+            ignore_whitespace: false,
+            ignore_whitespace_change: false,
+            ignore_whitespace_eol: false,
+            context_lines: 3,
+            interhunk_lines: 0,
+            algorithm: DiffAlgorithm::default(),
+            indent_heuristic: true,
+            renames: RenameSettings::default(),
+            recurse_submodules: false,
+            limits: DiffLimits::default(),
+        }
+    }
+}
+
+/// Caps on how much of a diff is loaded, so a minified bundle or a huge
+/// fixture can't blow up the response. Zero disables a limit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiffLimits {
+    /// Files with a side larger than this many bytes get no hunks.
+    pub max_file_size: u64,
+    /// Hunks past this many lines, context included, in one file are dropped.
+    pub max_file_lines: usize,
+    /// Hunks past this many added and deleted lines across the whole diff
+    /// are dropped. Lines already dropped from earlier files still count.
+    pub max_total_lines: usize,
+    /// Longer lines are cut to this many characters.
+    pub max_line_length: usize,
+}
+
+impl Default for DiffLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 5 * 1024 * 1024,
+            max_file_lines: 10_000,
+            max_total_lines: 100_000,
+            max_line_length: 5_000,
+        }
+    }
+}
+
+impl DiffLimits {
+    /// No limits, for loading a truncated file in full.
+    pub fn unlimited() -> Self {
+        Self {
+            max_file_size: 0,
+            max_file_lines: 0,
+            max_total_lines: 0,
+            max_line_length: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Minimal,
+    Patience,
+}
+
+/// What the compare side of a diff is taken from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompareMode {
+    /// The committed tree of the compare ref.
+    #[default]
+    Tree,
+    /// The working directory, including untracked files, against base.
+    Workdir,
+    /// Staged changes only: the index against base.
+    Index,
+    /// Unstaged changes only: the working directory against the index.
+    WorkdirIndex,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DiffFile {
     pub path: String,
     pub status: FileStatus,
     pub old_path: Option<String>,
+    /// Similarity percentage for renamed and copied files.
+    pub similarity: Option<u32>,
+    /// Mode on each side; `None` where the file doesn't exist on that side.
+    pub old_mode: Option<FileMode>,
+    pub new_mode: Option<FileMode>,
+    /// Blob OIDs and sizes; `None` where the file doesn't exist on that side.
+    pub old_oid: Option<String>,
+    pub new_oid: Option<String>,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    /// Set when libgit2 treats the content as binary; such files have no hunks.
+    pub binary: bool,
+    pub mime_type: Option<String>,
+    /// Encoding the content was decoded from (e.g. `UTF-8`, `Shift_JIS`);
+    /// `None` for binary files.
+    pub encoding: Option<String>,
+    /// The old side's encoding, when it differs from `encoding`.
+    pub old_encoding: Option<String>,
+    /// Some bytes couldn't be decoded and were replaced with U+FFFD.
+    pub lossy: bool,
+    /// Set when the file gained or lost its final newline.
+    pub eof_newline: Option<EofNewline>,
+    /// Set when every change in the file is a line-ending conversion. Such
+    /// files carry no hunks.
+    pub line_endings: Option<LineEndingChange>,
+    pub additions: usize,
+    pub deletions: usize,
     pub hunks: Vec<DiffHunk>,
+    /// Set when either side is a submodule (gitlink). Such files carry no
+    /// hunks.
+    pub submodule: Option<SubmoduleChange>,
+    /// Set when either side is a Git LFS pointer. Hunks then come from the
+    /// objects in the local LFS store, or are empty if any is missing.
+    pub lfs: Option<LfsChange>,
+    /// Why some of the file's hunks or line content were left out; empty
+    /// when the file is complete. Line counts still cover the whole file,
+    /// except with `FileSize`, where nothing was read.
+    pub truncated: Vec<TruncationReason>,
+}
+
+/// Which of the [`DiffLimits`] cut a file short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TruncationReason {
+    /// A side is over `max_file_size`; no hunks were loaded.
+    FileSize,
+    /// Hunks past `max_file_lines` were dropped.
+    FileLines,
+    /// Earlier files used up `max_total_lines`; hunks past it were dropped.
+    TotalLines,
+    /// Lines over `max_line_length` were cut short.
+    LineLength,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LfsChange {
+    /// `None` where that side isn't a pointer (or doesn't exist).
+    pub old: Option<LfsPointer>,
+    pub new: Option<LfsPointer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LfsPointer {
+    /// SHA-256 of the object.
+    pub oid: String,
+    pub size: u64,
+    /// The object is in the local LFS store.
+    pub present: bool,
+}
+
+/// The commits a submodule moved between.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmoduleChange {
+    /// `None` where the submodule doesn't exist on that side.
+    pub old_commit: Option<SubmoduleCommit>,
+    pub new_commit: Option<SubmoduleCommit>,
+    /// The submodule's own diff between the two commits, with paths relative
+    /// to the submodule. Only filled in with `recurse_submodules` set and
+    /// both commits present in the submodule's clone.
+    pub files: Option<Vec<DiffFile>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmoduleCommit {
+    pub oid: String,
+    /// `None` when the commit isn't in the submodule's local clone.
+    pub summary: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EofNewline {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineEndingChange {
+    pub from: LineEnding,
+    pub to: LineEnding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Mixed,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +326,47 @@ pub enum FileStatus {
     Modified,
     Deleted,
     Renamed,
+    Copied,
+    /// The entry changed kind, e.g. a regular file became a symlink.
+    Typechange,
+    /// The file exists but couldn't be read from the working directory.
+    Unreadable,
+    /// The index has an unresolved merge conflict for this path.
+    Conflicted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileMode {
+    Blob,
+    BlobExecutable,
+    Link,
+    /// A submodule (gitlink) entry.
+    Commit,
+    Tree,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RenameSettings {
+    pub renames: bool,
+    pub copies: bool,
+    /// Minimum similarity percentage for two files to be paired.
+    pub threshold: u16,
+    /// Maximum number of candidate files to compare; detection is skipped
+    /// for larger changesets, like git's `diff.renameLimit`.
+    pub limit: usize,
+}
+
+impl Default for RenameSettings {
+    fn default() -> Self {
+        Self {
+            renames: true,
+            copies: true,
+            threshold: 50,
+            limit: 1000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +386,19 @@ pub struct DiffLine {
     pub content: String,
     pub old_num: Option<u32>,
     pub new_num: Option<u32>,
+    /// The line is the last in its file and has no trailing newline.
+    pub no_newline: bool,
+    /// Parts of the line that differ from its paired line on the other side.
+    /// Empty for context lines and for lines without a close counterpart.
+    pub changes: Vec<TextRange>,
+}
+
+/// A half-open range within a line, in UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextRange {
+    pub start: u32,
+    pub end: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +409,221 @@ pub enum LineType {
     Context,
 }
 
+/// A run of lines from one version of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileLines {
+    /// 1-based number of the first line in `lines`.
+    pub start: u32,
+    pub lines: Vec<String>,
+    /// Line count of the whole file, so callers can tell when they've
+    /// reached the end.
+    pub total: u32,
+}
+
+/// Both versions of a file, for rendering previews of binary assets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobPair {
+    pub old: Option<BlobContent>,
+    pub new: Option<BlobContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobContent {
+    pub oid: String,
+    pub size: u64,
+    pub mime_type: Option<String>,
+    /// Raw content, base64-encoded.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitInfo {
+    pub oid: String,
+    pub summary: String,
+    pub author: String,
+    pub author_email: String,
+    /// Author time, in seconds since the Unix epoch.
+    pub time: i64,
+    pub parents: Vec<String>,
+    /// Paths changed relative to the first parent.
+    pub files: Vec<String>,
+}
+
+/// A patch file opened for review without a repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchSeries {
+    pub path: String,
+    /// One entry per mail for format-patch mboxes, or a single entry for a
+    /// plain diff.
+    pub patches: Vec<PatchEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchEntry {
+    /// Commit details from the mail headers; `None` for plain diffs.
+    pub commit: Option<CommitInfo>,
+    /// Full commit message, subject first.
+    pub message: String,
+    pub diff: DiffResult,
+}
+
+/// A compare commit recorded when a review round was done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewRound {
+    /// 1 for the first round, counting up.
+    pub number: u32,
+    pub oid: String,
+}
+
+/// One entry of a range-diff: a commit from the old series, the new series,
+/// or both when they were matched up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitPair {
+    pub status: PairStatus,
+    pub old: Option<CommitInfo>,
+    pub new: Option<CommitInfo>,
+    /// Diff between the two commits' messages and patches; empty unless
+    /// `Modified`.
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairStatus {
+    Unchanged,
+    Modified,
+    /// Only in the new series.
+    Added,
+    /// Dropped from the new series.
+    Removed,
+}
+
+/// Accept decisions for one file of a diff. Files that aren't listed are
+/// rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSelection {
+    pub path: String,
+    /// Source path of a rename or copy.
+    pub old_path: Option<String>,
+    /// `None` accepts the whole file, including mode changes and binary
+    /// content.
+    pub hunks: Option<Vec<HunkSelection>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HunkSelection {
+    /// Index into the file's `hunks`.
+    pub index: u32,
+    /// Indices into the hunk's `lines` to accept; `None` accepts the whole
+    /// hunk. Context lines are always kept.
+    pub lines: Option<Vec<u32>>,
+}
+
+/// Where accepted changes go.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ApplyTarget {
+    /// Commit on a new branch starting where the diff starts.
+    Branch { name: String, message: String },
+    /// Apply to the checkout that has base checked out, without committing.
+    Worktree,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyResult {
+    /// The new commit, for `ApplyTarget::Branch`.
+    pub commit: Option<String>,
+    /// Paths that received changes.
+    pub files: Vec<String>,
+}
+
+/// Outcome of merging compare into base in memory, without touching any
+/// worktree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergePreview {
+    pub base_oid: String,
+    pub compare_oid: String,
+    pub merge_base: Option<String>,
+    /// Compare is already contained in base; there is nothing to merge.
+    pub up_to_date: bool,
+    /// Base is an ancestor of compare, so the merge would be a fast-forward.
+    pub fast_forward: bool,
+    pub conflicts: Vec<ConflictFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeStrategy {
+    /// Move base to compare; fails if base has commits compare lacks.
+    FastForward,
+    /// Always create a merge commit, like `git merge --no-ff`.
+    Merge,
+    /// One commit on base with compare's changes, like `git merge --squash`.
+    Squash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeOutcome {
+    /// Base's new tip; `None` when the merge stopped on conflicts.
+    pub commit: Option<String>,
+    /// Conflicts that stopped the merge. Nothing was written if non-empty.
+    pub conflicts: Vec<ConflictFile>,
+    /// The compare branch was deleted after merging.
+    pub deleted_branch: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictFile {
+    pub path: String,
+    /// Missing on a side that added the file (add/add) or deleted it
+    /// (modify/delete).
+    pub ancestor: Option<ConflictSide>,
+    pub ours: Option<ConflictSide>,
+    pub theirs: Option<ConflictSide>,
+    pub binary: bool,
+    /// The file as `git merge` would leave it, with diff3-style conflict
+    /// markers. `None` for binary files and modify/delete conflicts.
+    pub merged: Option<String>,
+    pub hunks: Vec<ConflictHunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictSide {
+    pub path: String,
+    pub oid: String,
+    pub mode: Option<FileMode>,
+    /// `None` for binary content.
+    pub content: Option<String>,
+}
+
+/// One conflicted region of `ConflictFile::merged`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictHunk {
+    /// 1-based line of the `<<<<<<<` marker in the merged text.
+    pub start: u32,
+    /// 1-based line of the `>>>>>>>` marker.
+    pub end: u32,
+    pub ours: Vec<String>,
+    pub ancestor: Vec<String>,
+    pub theirs: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ReviewComment {
@@ -69,6 +640,17 @@ pub struct ReviewComment {
 pub struct RefInfo {
     pub name: String,
     pub ref_type: RefType,
+    pub worktree: Option<WorktreeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorktreeInfo {
+    pub path: String,
+    pub branch: Option<String>,
+    pub locked: bool,
+    pub lock_reason: Option<String>,
+    pub prunable: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,3 +660,10 @@ pub enum RefType {
     Tag,
     Worktree,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffSide {
+    Base,
+    Compare,
+}
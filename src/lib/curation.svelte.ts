@@ -0,0 +1,81 @@
+import type { DiffFile, DiffLine, FileSelection, FileSummary } from './types';
+
+// Rejected changes per file. Added lines are keyed by their new line number and
+// deleted lines by their old one, which stays stable while context is
+// expanded around them.
+let rejected: Record<string, string[]> = $state({});
+let excluded: Record<string, boolean> = $state({});
+
+function lineKey(line: DiffLine): string | null {
+  if (line.lineType === 'add') return `+${line.newNum}`;
+  if (line.lineType === 'delete') return `-${line.oldNum}`;
+  return null;
+}
+
+export function isLineRejected(path: string, line: DiffLine): boolean {
+  const key = lineKey(line);
+  return key !== null && (rejected[path]?.includes(key) ?? false);
+}
+
+export function toggleLine(path: string, line: DiffLine) {
+  const key = lineKey(line);
+  if (key === null) return;
+  const keys = rejected[path] ?? [];
+  rejected[path] = keys.includes(key) ? keys.filter((k) => k !== key) : [...keys, key];
+}
+
+export function isHunkAccepted(path: string, lines: DiffLine[]): boolean {
+  return lines.some((l) => lineKey(l) !== null && !isLineRejected(path, l));
+}
+
+// Reject every change in the hunk, or accept them all again if none are
+// accepted.
+export function toggleHunk(path: string, lines: DiffLine[]) {
+  const keys = lines.map(lineKey).filter((k): k is string => k !== null);
+  const current = rejected[path] ?? [];
+  rejected[path] = isHunkAccepted(path, lines)
+    ? [...new Set([...current, ...keys])]
+    : current.filter((k) => !keys.includes(k));
+}
+
+export function isFileExcluded(path: string): boolean {
+  return excluded[path] ?? false;
+}
+
+export function toggleFile(path: string) {
+  excluded[path] = !isFileExcluded(path);
+}
+
+export function clearCuration() {
+  rejected = {};
+  excluded = {};
+}
+
+// Accept decisions in the shape `apply_selection` expects. Files without
+// rejected lines are taken whole. A file that was cut short by the diff limits
+// is missing hunks the reviewer never saw, so its lines can only be picked
+// once it's loaded in full.
+export function buildSelection(
+  files: FileSummary[],
+  details: Record<string, DiffFile>,
+): FileSelection[] {
+  return files
+    .filter((file) => !isFileExcluded(file.path))
+    .map((file) => {
+      const detail = details[file.path];
+      if (!rejected[file.path]?.length || !detail) {
+        return { path: file.path, oldPath: file.oldPath, hunks: null };
+      }
+      if (detail.truncated.some((r) => r === 'fileLines' || r === 'totalLines')) {
+        throw new Error(`Load all of ${file.path} before accepting or rejecting its lines`);
+      }
+      return {
+        path: file.path,
+        oldPath: file.oldPath,
+        hunks: detail.hunks.map((hunk, index) => ({
+          index,
+          lines: hunk.lines.flatMap((line, i) => (isLineRejected(file.path, line) ? [] : [i])),
+        })),
+      };
+    });
+}
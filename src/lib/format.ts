@@ -0,0 +1,23 @@
+export function formatSize(size: number | null): string {
+  if (size === null) return '—';
+  if (size < 1024) return `${size} B`;
+  if (size < 1024 * 1024) return `${(size / 1024).toFixed(1)} KB`;
+  return `${(size / 1024 / 1024).toFixed(1)} MB`;
+}
+
+// Badge letters as `git status --short` prints them, so copied and
+// conflicted files don't both show as "C".
+const statusLetters: Record<string, string> = {
+  added: 'A',
+  modified: 'M',
+  deleted: 'D',
+  renamed: 'R',
+  copied: 'C',
+  typechange: 'T',
+  unreadable: 'X',
+  conflicted: 'U',
+};
+
+export function statusLetter(status: string): string {
+  return statusLetters[status] ?? status[0].toUpperCase();
+}
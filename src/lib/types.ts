@@ -1,10 +1,22 @@
-export type RefInfo = { name: string; refType: string };
+export type WorktreeInfo = {
+  path: string;
+  branch: string | null;
+  locked: boolean;
+  lockReason: string | null;
+  prunable: boolean;
+};
+
+export type RefInfo = { name: string; refType: string; worktree: WorktreeInfo | null };
+
+export type TextRange = { start: number; end: number };
 
 export type DiffLine = {
   lineType: string;
   content: string;
   oldNum: number | null;
   newNum: number | null;
+  noNewline: boolean;
+  changes: TextRange[];
 };
 
 export type DiffHunk = {
@@ -15,15 +27,250 @@ export type DiffHunk = {
   lines: DiffLine[];
 };
 
+export type LineEnding = 'lf' | 'crlf' | 'mixed';
+
+export type LineEndingChange = { from: LineEnding; to: LineEnding };
+
+export type FileMode = 'blob' | 'blobExecutable' | 'link' | 'commit' | 'tree';
+
 export type DiffFile = {
   path: string;
   status: string;
   oldPath: string | null;
+  similarity: number | null;
+  oldMode: FileMode | null;
+  newMode: FileMode | null;
+  oldOid: string | null;
+  newOid: string | null;
+  oldSize: number | null;
+  newSize: number | null;
+  binary: boolean;
+  mimeType: string | null;
+  encoding: string | null;
+  oldEncoding: string | null;
+  lossy: boolean;
+  eofNewline: 'added' | 'removed' | null;
+  lineEndings: LineEndingChange | null;
+  additions: number;
+  deletions: number;
   hunks: DiffHunk[];
+  submodule: SubmoduleChange | null;
+  lfs: LfsChange | null;
+  truncated: TruncationReason[];
+};
+
+export type TruncationReason = 'fileSize' | 'fileLines' | 'totalLines' | 'lineLength';
+
+export type LfsPointer = {
+  oid: string;
+  size: number;
+  present: boolean;
+};
+
+export type LfsChange = {
+  old: LfsPointer | null;
+  new: LfsPointer | null;
+};
+
+export type SubmoduleCommit = {
+  oid: string;
+  summary: string | null;
+};
+
+export type SubmoduleChange = {
+  oldCommit: SubmoduleCommit | null;
+  newCommit: SubmoduleCommit | null;
+  files: DiffFile[] | null;
+};
+
+export type DirStats = {
+  path: string;
+  files: number;
+  additions: number;
+  deletions: number;
+  children: DirStats[];
+};
+
+export type DiffStats = {
+  files: number;
+  additions: number;
+  deletions: number;
+  tree: DirStats;
+};
+
+export type CompareMode = 'tree' | 'workdir' | 'index' | 'workdirIndex';
+
+export type RenameSettings = {
+  renames: boolean;
+  copies: boolean;
+  threshold: number;
+  limit: number;
+};
+
+export type CommitInfo = {
+  oid: string;
+  summary: string;
+  author: string;
+  authorEmail: string;
+  time: number;
+  parents: string[];
+  files: string[];
+};
+
+export type CommitRange = { from: string; to: string };
+
+export type PatchEntry = {
+  commit: CommitInfo | null;
+  message: string;
+  diff: DiffResult;
+};
+
+export type PatchSeries = {
+  path: string;
+  patches: PatchEntry[];
+};
+
+export type ReviewRound = { number: number; oid: string };
+
+export type PairStatus = 'unchanged' | 'modified' | 'added' | 'removed';
+
+export type CommitPair = {
+  status: PairStatus;
+  old: CommitInfo | null;
+  new: CommitInfo | null;
+  hunks: DiffHunk[];
+};
+
+export type HunkSelection = { index: number; lines: number[] | null };
+
+export type FileSelection = {
+  path: string;
+  oldPath: string | null;
+  hunks: HunkSelection[] | null;
+};
+
+export type ApplyTarget = { kind: 'branch'; name: string; message: string } | { kind: 'worktree' };
+
+export type ApplyResult = { commit: string | null; files: string[] };
+
+export type ConflictSide = {
+  path: string;
+  oid: string;
+  mode: FileMode | null;
+  content: string | null;
+};
+
+export type ConflictHunk = {
+  start: number;
+  end: number;
+  ours: string[];
+  ancestor: string[];
+  theirs: string[];
+};
+
+export type ConflictFile = {
+  path: string;
+  ancestor: ConflictSide | null;
+  ours: ConflictSide | null;
+  theirs: ConflictSide | null;
+  binary: boolean;
+  merged: string | null;
+  hunks: ConflictHunk[];
+};
+
+export type MergePreview = {
+  baseOid: string;
+  compareOid: string;
+  mergeBase: string | null;
+  upToDate: boolean;
+  fastForward: boolean;
+  conflicts: ConflictFile[];
+};
+
+export type MergeStrategy = 'fastForward' | 'merge' | 'squash';
+
+export type MergeOutcome = {
+  commit: string | null;
+  conflicts: ConflictFile[];
+  deletedBranch: boolean;
+};
+
+export type DiffAlgorithm = 'myers' | 'minimal' | 'patience';
+
+export type DiffSettings = {
+  ignoreWhitespace: boolean;
+  ignoreWhitespaceChange: boolean;
+  ignoreWhitespaceEol: boolean;
+  contextLines: number;
+  interhunkLines: number;
+  algorithm: DiffAlgorithm;
+  indentHeuristic: boolean;
+  renames: RenameSettings;
+  recurseSubmodules: boolean;
+  limits: DiffLimits;
+};
+
+// Zero disables a limit.
+export type DiffLimits = {
+  maxFileSize: number;
+  maxFileLines: number;
+  maxTotalLines: number;
+  maxLineLength: number;
 };
 
 export type DiffResult = {
   baseRef: string;
   compareRef: string;
+  baseOid: string;
+  compareOid: string;
+  mode: CompareMode;
+  mergeBase: string | null;
+  ahead: number;
+  behind: number;
+  stats: DiffStats;
   files: DiffFile[];
 };
+
+export type FileSummary = {
+  path: string;
+  status: string;
+  oldPath: string | null;
+  similarity: number | null;
+  oldMode: FileMode | null;
+  newMode: FileMode | null;
+  oldOid: string | null;
+  newOid: string | null;
+  additions: number;
+  deletions: number;
+};
+
+export type DiffSummary = {
+  baseRef: string;
+  compareRef: string;
+  baseOid: string;
+  compareOid: string;
+  mode: CompareMode;
+  mergeBase: string | null;
+  ahead: number;
+  behind: number;
+  stats: DiffStats;
+  files: FileSummary[];
+};
+
+export type FileLines = {
+  start: number;
+  lines: string[];
+  total: number;
+};
+
+export type BlobContent = {
+  oid: string;
+  size: number;
+  mimeType: string | null;
+  data: string;
+};
+
+export type BlobPair = {
+  old: BlobContent | null;
+  new: BlobContent | null;
+};
@@ -1,21 +1,16 @@
-import type { DiffFile } from './types';
+import type { FileSummary } from './types';
+
+type ViewedFile = Pick<FileSummary, 'path' | 'oldOid' | 'newOid'>;
 
 let viewed: Record<string, string> = $state({});
 
-function hashFile(file: DiffFile): string {
-  let hash = 5381;
-  for (const hunk of file.hunks) {
-    for (const line of hunk.lines) {
-      const str = line.lineType + line.content;
-      for (let i = 0; i < str.length; i++) {
-        hash = ((hash << 5) + hash + str.charCodeAt(i)) | 0;
-      }
-    }
-  }
-  return hash.toString(36);
+// Blob OIDs identify both sides' content, so a file stays viewed until either
+// side changes, without needing its hunks loaded.
+function hashFile(file: ViewedFile): string {
+  return `${file.oldOid ?? ''}:${file.newOid ?? ''}`;
 }
 
-export function toggleViewed(file: DiffFile) {
+export function toggleViewed(file: ViewedFile) {
   const hash = hashFile(file);
   if (file.path in viewed && viewed[file.path] === hash) {
     delete viewed[file.path];
@@ -24,11 +19,11 @@ export function toggleViewed(file: DiffFile) {
   }
 }
 
-export function isViewed(file: DiffFile): boolean {
+export function isViewed(file: ViewedFile): boolean {
   return viewed[file.path] === hashFile(file);
 }
 
-export function reconcile(files: DiffFile[]) {
+export function reconcile(files: ViewedFile[]) {
   const paths = new Set(files.map((f) => f.path));
   for (const path of Object.keys(viewed)) {
     if (!paths.has(path)) {
@@ -42,7 +37,7 @@ export function reconcile(files: DiffFile[]) {
   }
 }
 
-export function viewedCount(files: DiffFile[]): { viewed: number; total: number } {
+export function viewedCount(files: ViewedFile[]): { viewed: number; total: number } {
   let count = 0;
   for (const file of files) {
     if (isViewed(file)) count++;